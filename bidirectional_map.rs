@@ -1,70 +1,527 @@
-use std::collections::HashMap;
+use std::borrow::Borrow;
+use std::collections::hash_map;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
 
-/// Bidirectional Map: Maintains 1-to-1 mapping between Keys and Values.
-/// Edge Case: Memory usage is 2x because we store data twice.
-struct BiMap {
-    forward: HashMap<String, String>,
-    reverse: HashMap<String, String>,
+/// What put() does when `right` is already bound to a different left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Last write wins: the old pair holding `right` is removed first.
+    Overwrite,
+    /// put() fails with RightTaken and the map is unchanged.
+    Reject,
+    /// put() succeeds but the map is unchanged: the existing pair wins.
+    KeepExisting,
 }
 
-impl BiMap {
-    fn new() -> Self {
+/// put() under OnConflict::Reject: `right` is bound to another left. Carries the refused pair.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RightTaken<L, R> {
+    pub left: L,
+    pub right: R,
+}
+
+impl<L, R> fmt::Display for RightTaken<L, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "right value is already bound to another left value")
+    }
+}
+
+impl<L: fmt::Debug, R: fmt::Debug> Error for RightTaken<L, R> {}
+
+/// One direction of a two-map bimap: a HashMap here, a BTreeMap in OrderedBiMap.
+/// Lets both share put_pair() instead of each keeping its own conflict handling.
+pub trait SideMap<K, V> {
+    fn get(&self, key: &K) -> Option<&V>;
+    fn insert(&mut self, key: K, value: V) -> Option<V>;
+    fn remove(&mut self, key: &K) -> Option<V>;
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K: Hash + Eq, V> SideMap<K, V> for HashMap<K, V> {
+    fn get(&self, key: &K) -> Option<&V> {
+        HashMap::get(self, key)
+    }
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        HashMap::insert(self, key, value)
+    }
+    fn remove(&mut self, key: &K) -> Option<V> {
+        HashMap::remove(self, key)
+    }
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+}
+
+impl<K: Ord, V> SideMap<K, V> for BTreeMap<K, V> {
+    fn get(&self, key: &K) -> Option<&V> {
+        BTreeMap::get(self, key)
+    }
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        BTreeMap::insert(self, key, value)
+    }
+    fn remove(&mut self, key: &K) -> Option<V> {
+        BTreeMap::remove(self, key)
+    }
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+}
+
+/// Inserts a left-right pair into `forward` and `reverse`, resolving a taken right by `on_conflict`.
+/// Edge Case 1: New Left -> Simple insert in both maps.
+/// Edge Case 2: Overwrite Left -> Must remove OLD right from reverse map to prevent stale data.
+/// Edge Case 3: Duplicate Right -> right is bound to another left; on_conflict decides.
+///              Overwrite must remove that left's forward entry too, or it would
+///              still point at right and the map would stop being 1-to-1.
+pub fn put_pair<L, R, F, B>(forward: &mut F, reverse: &mut B, on_conflict: OnConflict, left: L, right: R) -> Result<(), RightTaken<L, R>>
+where
+    L: Eq + Clone,
+    R: Clone,
+    F: SideMap<L, R>,
+    B: SideMap<R, L>,
+{
+    let taken = reverse.get(&right).is_some_and(|holder| *holder != left);
+    if taken {
+        match on_conflict {
+            OnConflict::Reject => return Err(RightTaken { left, right }),
+            OnConflict::KeepExisting => return Ok(()),
+            OnConflict::Overwrite => {
+                if let Some(holder) = reverse.remove(&right) {
+                    forward.remove(&holder);
+                }
+            }
+        }
+    }
+    // Insert into forward map. If left existed, it returns the old right.
+    if let Some(old_right) = forward.insert(left.clone(), right.clone()) {
+        // EDGE CASE FIX: Remove the old reverse mapping to prevent stale data
+        // If we didn't do this, get_by_right(old_right) would still return the left!
+        reverse.remove(&old_right);
+    }
+    // Insert the new reverse mapping
+    reverse.insert(right, left);
+    Ok(())
+}
+
+/// Bidirectional Map: Maintains 1-to-1 mapping between Left and Right values
+/// (e.g. domain name <-> IP address).
+/// Invariant: forward and reverse hold exactly the same pairs.
+/// Edge Case: Memory usage is 2x because we store data twice
+///             (CompactBiMap in compact_bimap.rs stores each pair once).
+pub struct BiMap<L, R> {
+    forward: HashMap<L, R>,
+    reverse: HashMap<R, L>,
+    on_conflict: OnConflict,
+}
+
+impl<L: Hash + Eq + Clone, R: Hash + Eq + Clone> Default for BiMap<L, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<L: Hash + Eq + Clone, R: Hash + Eq + Clone> BiMap<L, R> {
+    pub fn new() -> Self {
         Self {
             forward: HashMap::new(),
             reverse: HashMap::new(),
+            on_conflict: OnConflict::Overwrite,
         }
     }
 
-    /// Inserts a key-value pair.
-    /// Edge Case 1: New Key -> Simple insert in both maps.
-    /// Edge Case 2: Overwrite Key -> Must remove OLD value from reverse map to prevent stale data.
-    /// Edge Case 3: Duplicate Value -> If value exists for another key, it overwrites the reverse mapping (Last Write Wins).
-    fn put(&mut self, key: String, value: String) {
-        // Insert into forward map. If key existed, it returns the old value.
-        if let Some(old_value) = self.forward.insert(key.clone(), value.clone()) {
-            // EDGE CASE FIX: Remove the old reverse mapping to prevent stale data
-            // If we didn't do this, get_by_value(old_value) would still return the key!
-            self.reverse.remove(&old_value);
-        }
-        // Insert the new reverse mapping
-        self.reverse.insert(value, key);
+    /// Choose how put() handles a right value already bound elsewhere (default Overwrite).
+    pub fn with_conflict_policy(mut self, on_conflict: OnConflict) -> Self {
+        self.on_conflict = on_conflict;
+        self
     }
 
-    /// Lookup by Key.
-    /// Edge Case: Key does not exist -> Returns "Key not found".
-    fn get_by_key(&self, key: &str) -> String {
-        match self.forward.get(key) {
-            Some(value) => value.clone(),
-            None => String::from("Key not found"),
-        }
+    /// Inserts a left-right pair (see put_pair for the edge cases).
+    pub fn put(&mut self, left: L, right: R) -> Result<(), RightTaken<L, R>> {
+        put_pair(&mut self.forward, &mut self.reverse, self.on_conflict, left, right)
     }
 
-    /// Lookup by Value (Reverse Lookup).
-    /// Edge Case: Value does not exist -> Returns "Value not found".
-    fn get_by_value(&self, value: &str) -> String {
-        match self.reverse.get(value) {
-            Some(key) => key.clone(),
-            None => String::from("Value not found"),
-        }
+    /// Lookup by Left.
+    /// Edge Case: Left does not exist -> None (no sentinel that could clash with a real value).
+    pub fn get_by_left<Q>(&self, left: &Q) -> Option<&R>
+    where
+        L: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.forward.get(left)
+    }
+
+    /// Lookup by Right (Reverse Lookup).
+    /// Edge Case: Right does not exist -> None.
+    pub fn get_by_right<Q>(&self, right: &Q) -> Option<&L>
+    where
+        R: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.reverse.get(right)
+    }
+
+    pub fn contains_left<Q>(&self, left: &Q) -> bool
+    where
+        L: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.forward.contains_key(left)
+    }
+
+    pub fn contains_right<Q>(&self, right: &Q) -> bool
+    where
+        R: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.reverse.contains_key(right)
+    }
+
+    /// Removes the pair holding `left` from both maps and returns its right.
+    /// Edge Case: Left does not exist -> None, nothing changes.
+    pub fn remove_by_left<Q>(&mut self, left: &Q) -> Option<R>
+    where
+        L: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let right = self.forward.remove(left)?;
+        self.reverse.remove(&right);
+        Some(right)
+    }
+
+    /// Removes the pair holding `right` from both maps and returns its left.
+    pub fn remove_by_right<Q>(&mut self, right: &Q) -> Option<L>
+    where
+        R: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let left = self.reverse.remove(right)?;
+        self.forward.remove(&left);
+        Some(left)
+    }
+
+    /// Number of pairs (forward and reverse always hold the same count).
+    pub fn len(&self) -> usize {
+        self.forward.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.forward.is_empty()
+    }
+
+    /// All pairs as (left, right), in no particular order.
+    pub fn iter(&self) -> hash_map::Iter<'_, L, R> {
+        self.forward.iter()
+    }
+
+    /// Removes and yields every pair. The reverse map is cleared up front, and the
+    /// forward map is empty once the iterator is dropped, even if not fully consumed.
+    pub fn drain(&mut self) -> hash_map::Drain<'_, L, R> {
+        self.reverse.clear();
+        self.forward.drain()
+    }
+
+    /// Keeps only the pairs for which `keep(left, right)` is true.
+    pub fn retain<F: FnMut(&L, &R) -> bool>(&mut self, mut keep: F) {
+        let reverse = &mut self.reverse;
+        self.forward.retain(|left, right| {
+            let kept = keep(left, right);
+            if !kept {
+                reverse.remove(right);
+            }
+            kept
+        });
     }
 }
 
 fn main() {
     println!("--- Test 1: Basic Put and Get ---");
     let mut map = BiMap::new();
-    map.put("google.com".to_string(), "1.2.3.4".to_string());
-    
-    println!("Key 'google.com' -> Value: {}", map.get_by_key("google.com"));
-    println!("Value '1.2.3.4' -> Key: {}", map.get_by_value("1.2.3.4"));
+    let _ = map.put("google.com".to_string(), "1.2.3.4".to_string());
+
+    println!("Key 'google.com' -> Value: {:?}", map.get_by_left("google.com"));
+    println!("Value '1.2.3.4' -> Key: {:?}", map.get_by_right("1.2.3.4"));
 
     println!("\n--- Test 2: Missing Keys/Values ---");
-    println!("Key 'yahoo.com' -> Value: {}", map.get_by_key("yahoo.com"));
-    println!("Value '9.9.9.9' -> Key: {}", map.get_by_value("9.9.9.9"));
+    println!("Key 'yahoo.com' -> Value: {:?}", map.get_by_left("yahoo.com"));
+    println!("Value '9.9.9.9' -> Key: {:?}", map.get_by_right("9.9.9.9"));
 
     println!("\n--- Test 3: Overwrite (Update) ---");
     // Edge Case: Updating a key must clean up the reverse map.
-    map.put("google.com".to_string(), "5.6.7.8".to_string());
-    println!("Updated 'google.com' -> Value: {}", map.get_by_key("google.com"));
-    println!("New Value '5.6.7.8' -> Key: {}", map.get_by_value("5.6.7.8"));
-    println!("Old Value '1.2.3.4' -> Key: {}", map.get_by_value("1.2.3.4")); // Should be Not Found
-}
\ No newline at end of file
+    let _ = map.put("google.com".to_string(), "5.6.7.8".to_string());
+    println!("Updated 'google.com' -> Value: {:?}", map.get_by_left("google.com"));
+    println!("New Value '5.6.7.8' -> Key: {:?}", map.get_by_right("5.6.7.8"));
+    println!("Old Value '1.2.3.4' -> Key: {:?}", map.get_by_right("1.2.3.4")); // Should be None
+
+    println!("\n--- Test 4: Non-String Types ---");
+    let mut ports: BiMap<u16, &str> = BiMap::new();
+    let _ = ports.put(443, "https");
+    println!("Port 443 -> Service: {:?}", ports.get_by_left(&443));
+    println!("Service 'https' -> Port: {:?}", ports.get_by_right("https"));
+
+    println!("\n--- Test 5: Duplicate Value (Conflict Policies) ---");
+    // Edge Case: '5.6.7.8' already belongs to 'google.com'
+    for policy in [OnConflict::Overwrite, OnConflict::Reject, OnConflict::KeepExisting] {
+        let mut dns = BiMap::new().with_conflict_policy(policy);
+        let _ = dns.put("google.com", "5.6.7.8");
+        let result = dns.put("mirror.com", "5.6.7.8");
+        println!(
+            "{:?}: put -> {}, 'google.com' -> {:?}, '5.6.7.8' -> {:?}",
+            policy,
+            result.map_or_else(|e| e.to_string(), |()| "ok".to_string()),
+            dns.get_by_left("google.com"),
+            dns.get_by_right("5.6.7.8")
+        );
+    }
+
+    println!("\n--- Test 6: Remove, Retain and Iterate ---");
+    let mut hosts = BiMap::new();
+    for (name, ip) in [("web", "10.0.0.1"), ("db", "10.0.0.2"), ("cache", "10.0.0.3"), ("old", "10.0.0.9")] {
+        let _ = hosts.put(name, ip);
+    }
+    // Expiring a DNS record must clear both directions
+    println!("Remove 'old' -> {:?}", hosts.remove_by_left("old"));
+    println!("Contains 'old'? {}, IP '10.0.0.9'? {}", hosts.contains_left("old"), hosts.contains_right("10.0.0.9"));
+    println!("Remove IP '10.0.0.3' -> {:?}", hosts.remove_by_right("10.0.0.3"));
+    hosts.retain(|name, _| *name != "db");
+    let mut listed: Vec<_> = hosts.iter().collect();
+    listed.sort();
+    println!("{} pair(s) left: {:?}", hosts.len(), listed);
+    let drained: Vec<_> = hosts.drain().collect();
+    println!("Drained {:?}, empty now? {}", drained, hosts.is_empty());
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookups_both_directions() {
+        let mut map = BiMap::new();
+        map.put("google.com".to_string(), "1.2.3.4".to_string()).unwrap();
+        assert_eq!(map.get_by_left("google.com"), Some(&"1.2.3.4".to_string()));
+        assert_eq!(map.get_by_right("1.2.3.4"), Some(&"google.com".to_string()));
+        assert_eq!(map.get_by_left("yahoo.com"), None);
+        assert_eq!(map.get_by_right("9.9.9.9"), None);
+    }
+
+    // The old API answered a miss with "Key not found"; a real value with that text was indistinguishable
+    #[test]
+    fn test_missing_is_distinct_from_any_value() {
+        let mut map = BiMap::new();
+        map.put("Key not found", "Value not found").unwrap();
+        assert_eq!(map.get_by_left("Key not found"), Some(&"Value not found"));
+        assert_eq!(map.get_by_left("other"), None);
+    }
+
+    #[test]
+    fn test_overwrite_left_drops_old_reverse_entry() {
+        let mut map: BiMap<u32, char> = BiMap::new();
+        map.put(1, 'a').unwrap();
+        map.put(1, 'b').unwrap();
+        assert_eq!(map.get_by_left(&1), Some(&'b'));
+        assert_eq!(map.get_by_right(&'b'), Some(&1));
+        assert_eq!(map.get_by_right(&'a'), None);
+    }
+
+    // forward and reverse must describe exactly the same pairs
+    pub(crate) fn assert_sides_in_sync<'a, L, R, B, I>(forward: I, reverse: &B)
+    where
+        L: PartialEq + fmt::Debug + 'a,
+        R: 'a,
+        B: SideMap<R, L>,
+        I: ExactSizeIterator<Item = (&'a L, &'a R)>,
+    {
+        assert_eq!(forward.len(), reverse.len());
+        for (left, right) in forward {
+            assert_eq!(reverse.get(right), Some(left));
+        }
+    }
+
+    fn assert_in_sync<L: Hash + Eq + Clone + fmt::Debug, R: Hash + Eq + Clone>(map: &BiMap<L, R>) {
+        assert_sides_in_sync(map.forward.iter(), &map.reverse);
+    }
+
+    fn dns(policy: OnConflict) -> BiMap<&'static str, &'static str> {
+        let mut map = BiMap::new().with_conflict_policy(policy);
+        map.put("google.com", "1.2.3.4").unwrap();
+        map.put("yahoo.com", "5.6.7.8").unwrap();
+        map
+    }
+
+    #[test]
+    fn test_overwrite_removes_old_pair() {
+        let mut map = dns(OnConflict::Overwrite);
+        assert_eq!(map.put("mirror.com", "1.2.3.4"), Ok(()));
+        assert_eq!(map.get_by_right("1.2.3.4"), Some(&"mirror.com"));
+        // The old holder must not keep pointing at the stolen value
+        assert_eq!(map.get_by_left("google.com"), None);
+
+        // Both sides taken: yahoo.com's old value and 1.2.3.4's old holder both go
+        assert_eq!(map.put("yahoo.com", "1.2.3.4"), Ok(()));
+        assert_eq!(map.get_by_left("mirror.com"), None);
+        assert_eq!(map.get_by_right("5.6.7.8"), None);
+        assert_eq!(map.forward.len(), 1);
+        assert_in_sync(&map);
+    }
+
+    #[test]
+    fn test_reject_leaves_map_unchanged() {
+        let mut map = dns(OnConflict::Reject);
+        assert_eq!(map.put("mirror.com", "1.2.3.4"), Err(RightTaken { left: "mirror.com", right: "1.2.3.4" }));
+        assert_eq!(map.put("yahoo.com", "1.2.3.4"), Err(RightTaken { left: "yahoo.com", right: "1.2.3.4" }));
+        assert_eq!(map.get_by_left("yahoo.com"), Some(&"5.6.7.8"));
+        assert_eq!(map.get_by_right("1.2.3.4"), Some(&"google.com"));
+        assert_eq!(map.get_by_left("mirror.com"), None);
+        // Re-putting an existing pair is not a conflict
+        assert_eq!(map.put("google.com", "1.2.3.4"), Ok(()));
+        assert_in_sync(&map);
+    }
+
+    #[test]
+    fn test_keep_existing_ignores_conflicting_put() {
+        let mut map = dns(OnConflict::KeepExisting);
+        assert_eq!(map.put("mirror.com", "1.2.3.4"), Ok(()));
+        assert_eq!(map.get_by_right("1.2.3.4"), Some(&"google.com"));
+        assert_eq!(map.get_by_left("mirror.com"), None);
+        // No conflict: a left moving to a free right still works
+        assert_eq!(map.put("google.com", "9.9.9.9"), Ok(()));
+        assert_eq!(map.get_by_right("1.2.3.4"), None);
+        assert_in_sync(&map);
+    }
+
+    // xorshift64: deterministic, so a failing seed can be replayed.
+    // Shared with the compact_bimap and ordered_bimap tests.
+    pub(crate) struct Rng(pub(crate) u64);
+
+    impl Rng {
+        pub(crate) fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+    }
+
+    // Property: for every policy and any sequence of puts over small domains (so
+    // collisions on both sides are common), the map stays in sync and matches a
+    // naive model - a list of pairs updated by the policy's definition.
+    #[test]
+    fn test_property_every_policy_stays_one_to_one() {
+        for policy in [OnConflict::Overwrite, OnConflict::Reject, OnConflict::KeepExisting] {
+            for seed in 1..=200u64 {
+                let mut rng = Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+                let mut map = BiMap::new().with_conflict_policy(policy);
+                let mut model: Vec<(u64, u64)> = Vec::new();
+                for _ in 0..100 {
+                    let (left, right) = (rng.below(8), rng.below(8));
+                    let holder = model.iter().find(|pair| pair.1 == right).map(|pair| pair.0);
+                    let conflict = holder.is_some_and(|holder| holder != left);
+                    let expected = if conflict && policy == OnConflict::Reject {
+                        Err(RightTaken { left, right })
+                    } else {
+                        if !conflict || policy == OnConflict::Overwrite {
+                            model.retain(|pair| pair.0 != left && pair.1 != right);
+                            model.push((left, right));
+                        }
+                        Ok(())
+                    };
+                    assert_eq!(map.put(left, right), expected, "{:?} seed {}", policy, seed);
+                    assert_in_sync(&map);
+                    assert_eq!(map.forward.len(), model.len(), "{:?} seed {}", policy, seed);
+                    for &(l, r) in &model {
+                        assert_eq!(map.get_by_left(&l), Some(&r), "{:?} seed {}", policy, seed);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_remove_clears_both_directions() {
+        let mut map = dns(OnConflict::Reject);
+        assert_eq!(map.remove_by_left("google.com"), Some("1.2.3.4"));
+        assert!(!map.contains_left("google.com"));
+        assert!(!map.contains_right("1.2.3.4"));
+        assert_eq!(map.remove_by_left("google.com"), None);
+
+        assert_eq!(map.remove_by_right("5.6.7.8"), Some("yahoo.com"));
+        assert_eq!(map.get_by_left("yahoo.com"), None);
+        assert!(map.is_empty());
+
+        // The freed value is no longer a conflict
+        assert_eq!(map.put("mirror.com", "1.2.3.4"), Ok(()));
+        assert_in_sync(&map);
+    }
+
+    #[test]
+    fn test_iter_retain_and_drain() {
+        let mut map: BiMap<u32, String> = BiMap::new();
+        for n in 0..10 {
+            map.put(n, format!("host-{}", n)).unwrap();
+        }
+        assert_eq!(map.len(), 10);
+        let mut pairs: Vec<_> = map.iter().map(|(l, r)| (*l, r.clone())).collect();
+        pairs.sort();
+        assert_eq!(pairs[3], (3, "host-3".to_string()));
+
+        map.retain(|left, _| left % 2 == 0);
+        assert_eq!(map.len(), 5);
+        assert!(!map.contains_right("host-3"));
+        assert_eq!(map.get_by_right("host-4"), Some(&4));
+        assert_in_sync(&map);
+
+        let mut drained: Vec<_> = map.drain().collect();
+        drained.sort();
+        assert_eq!(drained.len(), 5);
+        assert_eq!(drained[0], (0, "host-0".to_string()));
+        assert!(map.is_empty());
+        assert_eq!(map.get_by_right("host-0"), None);
+        assert_in_sync(&map);
+    }
+
+    // Property: puts mixed with removals from either side and retain never leave
+    // a pair visible from only one direction.
+    #[test]
+    fn test_property_removals_stay_in_sync() {
+        for seed in 1..=200u64 {
+            let mut rng = Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+            let mut map = BiMap::new();
+            let mut model: Vec<(u64, u64)> = Vec::new();
+            for _ in 0..100 {
+                let (left, right) = (rng.below(8), rng.below(8));
+                match rng.below(4) {
+                    0 => {
+                        let expected = model.iter().position(|pair| pair.0 == left).map(|i| model.remove(i).1);
+                        assert_eq!(map.remove_by_left(&left), expected, "seed {}", seed);
+                    }
+                    1 => {
+                        let expected = model.iter().position(|pair| pair.1 == right).map(|i| model.remove(i).0);
+                        assert_eq!(map.remove_by_right(&right), expected, "seed {}", seed);
+                    }
+                    2 => {
+                        let cut = rng.below(8);
+                        map.retain(|l, r| l + r != cut);
+                        model.retain(|pair| pair.0 + pair.1 != cut);
+                    }
+                    _ => {
+                        map.put(left, right).unwrap();
+                        model.retain(|pair| pair.0 != left && pair.1 != right);
+                        model.push((left, right));
+                    }
+                }
+                assert_in_sync(&map);
+                assert_eq!(map.len(), model.len(), "seed {}", seed);
+                for &(l, r) in &model {
+                    assert!(map.contains_left(&l) && map.contains_right(&r), "seed {}", seed);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,517 @@
+// Interchangeable eviction policies behind one Cache trait.
+//
+// LRU, 2Q and ARC keep their entries in a LinkedSlab with a fixed set of
+// numbered lists (see linked_slab.rs, which LFUCache's frequency buckets
+// are built on too). LFU is LFUCache itself.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use super::linked_slab::LinkedSlab;
+use super::LFUCache;
+
+/// What every policy offers. put() returns the entries it pushed out, like LFUCache::put.
+pub trait Cache<K, V> {
+    fn get(&mut self, key: &K) -> Option<&V>;
+    fn put(&mut self, key: K, value: V) -> Vec<(K, V)>;
+    /// Resident entries (ghost entries kept by ARC and 2Q do not count).
+    fn len(&self) -> usize;
+    fn capacity(&self) -> usize;
+    fn name(&self) -> &'static str;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Policy picker, e.g. for running one trace against every policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    Lru,
+    Lfu,
+    Arc,
+    TwoQueue,
+}
+
+impl Policy {
+    pub const ALL: [Policy; 4] = [Policy::Lru, Policy::Lfu, Policy::Arc, Policy::TwoQueue];
+
+    pub fn build<K, V>(self, capacity: usize) -> Box<dyn Cache<K, V>>
+    where
+        K: Hash + Eq + Clone + 'static,
+        V: 'static,
+    {
+        match self {
+            Policy::Lru => Box::new(LruCache::new(capacity)),
+            Policy::Lfu => Box::new(LFUCache::new(capacity)),
+            Policy::Arc => Box::new(ArcCache::new(capacity)),
+            Policy::TwoQueue => Box::new(TwoQueueCache::new(capacity)),
+        }
+    }
+}
+
+impl std::str::FromStr for Policy {
+    type Err = String;
+
+    /// Accepts lru, lfu, arc and 2q (any case).
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "lru" => Ok(Policy::Lru),
+            "lfu" => Ok(Policy::Lfu),
+            "arc" => Ok(Policy::Arc),
+            "2q" | "twoqueue" => Ok(Policy::TwoQueue),
+            other => Err(format!("unknown policy '{}' (expected lru, lfu, arc or 2q)", other)),
+        }
+    }
+}
+
+// Slab item for the policies below. Ghost entries (ARC's B1/B2, 2Q's A1out)
+// remember a key that was evicted recently, so value is None.
+struct Slot<K, V> {
+    key: K,
+    value: Option<V>,
+}
+
+impl<K, V> Slot<K, V> {
+    fn resident(key: K, value: V) -> Self {
+        Slot {
+            key,
+            value: Some(value),
+        }
+    }
+
+    fn value(&self) -> &V {
+        self.value.as_ref().expect("resident slot should hold a value")
+    }
+}
+
+/// Plain least-recently-used: one list, hits move to the head, the tail is evicted.
+pub struct LruCache<K, V> {
+    capacity: usize,
+    key_map: HashMap<K, usize>,
+    slab: LinkedSlab<Slot<K, V>>,
+}
+
+const LRU: usize = 0;
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        LruCache {
+            capacity,
+            key_map: HashMap::new(),
+            slab: LinkedSlab::new(1),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> Cache<K, V> for LruCache<K, V> {
+    fn get(&mut self, key: &K) -> Option<&V> {
+        let idx = *self.key_map.get(key)?;
+        self.slab.move_to_front(LRU, idx);
+        Some(self.slab.get(idx).value())
+    }
+
+    fn put(&mut self, key: K, value: V) -> Vec<(K, V)> {
+        if self.capacity == 0 {
+            return vec![(key, value)];
+        }
+        if let Some(&idx) = self.key_map.get(&key) {
+            self.slab.get_mut(idx).value = Some(value);
+            self.slab.move_to_front(LRU, idx);
+            return Vec::new();
+        }
+        let mut evicted = Vec::new();
+        if self.key_map.len() == self.capacity {
+            let tail = self.slab.back(LRU).expect("full cache should have a tail");
+            let victim = self.slab.remove(tail);
+            self.key_map.remove(&victim.key);
+            evicted.push((victim.key, victim.value.expect("resident slot")));
+        }
+        let idx = self.slab.push_front(LRU, Slot::resident(key.clone(), value));
+        self.key_map.insert(key, idx);
+        evicted
+    }
+
+    fn len(&self) -> usize {
+        self.key_map.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn name(&self) -> &'static str {
+        "LRU"
+    }
+}
+
+/// Today's LFUCache, unchanged, behind the common trait.
+impl<K: Hash + Eq + Clone, V> Cache<K, V> for LFUCache<K, V> {
+    fn get(&mut self, key: &K) -> Option<&V> {
+        LFUCache::get(self, key)
+    }
+
+    fn put(&mut self, key: K, value: V) -> Vec<(K, V)> {
+        LFUCache::put(self, key, value)
+    }
+
+    fn len(&self) -> usize {
+        LFUCache::len(self)
+    }
+
+    fn capacity(&self) -> usize {
+        LFUCache::capacity(self)
+    }
+
+    fn name(&self) -> &'static str {
+        "LFU"
+    }
+}
+
+// 2Q lists
+const A1_IN: usize = 0; // FIFO of keys seen once, resident
+const A1_OUT: usize = 1; // ghosts of keys pushed out of A1in
+const AM: usize = 2; // LRU of keys seen again
+
+/// 2Q (Johnson & Shasha). New keys wait in a small FIFO (A1in, 1/4 of capacity).
+/// Only a key requested again after leaving A1in - found in the A1out ghost list
+/// (1/2 of capacity) - is promoted to the main LRU (Am). One-hit scans never reach Am.
+pub struct TwoQueueCache<K, V> {
+    capacity: usize,
+    in_capacity: usize,
+    out_capacity: usize,
+    key_map: HashMap<K, usize>,
+    slab: LinkedSlab<Slot<K, V>>,
+}
+
+impl<K: Hash + Eq + Clone, V> TwoQueueCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        TwoQueueCache {
+            capacity,
+            in_capacity: (capacity / 4).max(1),
+            out_capacity: (capacity / 2).max(1),
+            key_map: HashMap::new(),
+            slab: LinkedSlab::new(3),
+        }
+    }
+
+    fn resident_len(&self) -> usize {
+        self.slab.list_len(A1_IN) + self.slab.list_len(AM)
+    }
+
+    // Free one resident slot: A1in's oldest key becomes a ghost if A1in is over
+    // its share, otherwise Am's LRU key goes for good
+    fn reclaim(&mut self) -> (K, V) {
+        if self.slab.list_len(A1_IN) > self.in_capacity || self.slab.list_len(AM) == 0 {
+            let tail = self.slab.back(A1_IN).expect("A1in should not be empty");
+            let value = self.slab.get_mut(tail).value.take().expect("resident slot");
+            self.slab.move_to_front(A1_OUT, tail);
+            let key = self.slab.get(tail).key.clone();
+            if self.slab.list_len(A1_OUT) > self.out_capacity {
+                let oldest = self.slab.back(A1_OUT).expect("A1out should not be empty");
+                let ghost = self.slab.remove(oldest);
+                self.key_map.remove(&ghost.key);
+            }
+            (key, value)
+        } else {
+            let tail = self.slab.back(AM).expect("Am should not be empty");
+            let victim = self.slab.remove(tail);
+            self.key_map.remove(&victim.key);
+            (victim.key, victim.value.expect("resident slot"))
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> Cache<K, V> for TwoQueueCache<K, V> {
+    fn get(&mut self, key: &K) -> Option<&V> {
+        let idx = *self.key_map.get(key)?;
+        match self.slab.list_of(idx) {
+            AM => self.slab.move_to_front(AM, idx),
+            A1_IN => {} // FIFO: a hit in A1in does not reorder it
+            _ => return None, // ghost: remembered, not stored
+        }
+        Some(self.slab.get(idx).value())
+    }
+
+    fn put(&mut self, key: K, value: V) -> Vec<(K, V)> {
+        if self.capacity == 0 {
+            return vec![(key, value)];
+        }
+        let mut evicted = Vec::new();
+        if let Some(&idx) = self.key_map.get(&key) {
+            match self.slab.list_of(idx) {
+                AM => {
+                    self.slab.get_mut(idx).value = Some(value);
+                    self.slab.move_to_front(AM, idx);
+                }
+                A1_IN => self.slab.get_mut(idx).value = Some(value),
+                _ => {
+                    // Ghost hit: the key came back after leaving A1in, so it is hot
+                    self.slab.remove(idx);
+                    self.key_map.remove(&key);
+                    if self.resident_len() >= self.capacity {
+                        evicted.push(self.reclaim());
+                    }
+                    let idx = self.slab.push_front(AM, Slot::resident(key.clone(), value));
+                    self.key_map.insert(key, idx);
+                }
+            }
+            return evicted;
+        }
+        if self.resident_len() >= self.capacity {
+            evicted.push(self.reclaim());
+        }
+        let idx = self.slab.push_front(A1_IN, Slot::resident(key.clone(), value));
+        self.key_map.insert(key, idx);
+        evicted
+    }
+
+    fn len(&self) -> usize {
+        self.resident_len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn name(&self) -> &'static str {
+        "2Q"
+    }
+}
+
+// ARC lists
+const T1: usize = 0; // resident, seen once recently
+const T2: usize = 1; // resident, seen at least twice
+const B1: usize = 2; // ghosts evicted from T1
+const B2: usize = 3; // ghosts evicted from T2
+
+/// Adaptive Replacement Cache (Megiddo & Modha). Splits capacity between a
+/// recency list (T1) and a frequency list (T2) and keeps ghosts of what each
+/// evicted (B1, B2). A ghost hit in B1 grows T1's target size `p`, a ghost hit
+/// in B2 shrinks it, so the split follows the workload.
+pub struct ArcCache<K, V> {
+    capacity: usize,
+    p: usize,
+    key_map: HashMap<K, usize>,
+    slab: LinkedSlab<Slot<K, V>>,
+}
+
+impl<K: Hash + Eq + Clone, V> ArcCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        ArcCache {
+            capacity,
+            p: 0,
+            key_map: HashMap::new(),
+            slab: LinkedSlab::new(4),
+        }
+    }
+
+    /// Current target size for T1.
+    pub fn target_recent(&self) -> usize {
+        self.p
+    }
+
+    fn resident_len(&self) -> usize {
+        self.slab.list_len(T1) + self.slab.list_len(T2)
+    }
+
+    // REPLACE: demote the LRU of T1 or T2 to its ghost list and return its value
+    fn replace(&mut self, hit_in_b2: bool) -> (K, V) {
+        let t1_len = self.slab.list_len(T1);
+        let from_t1 = t1_len > 0
+            && (t1_len > self.p || (hit_in_b2 && t1_len == self.p) || self.slab.list_len(T2) == 0);
+        let (from, to) = if from_t1 { (T1, B1) } else { (T2, B2) };
+        let tail = self.slab.back(from).expect("resident list should not be empty");
+        let value = self.slab.get_mut(tail).value.take().expect("resident slot");
+        self.slab.move_to_front(to, tail);
+        (self.slab.get(tail).key.clone(), value)
+    }
+
+    fn drop_ghost(&mut self, list: usize) {
+        if let Some(tail) = self.slab.back(list) {
+            let ghost = self.slab.remove(tail);
+            self.key_map.remove(&ghost.key);
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> Cache<K, V> for ArcCache<K, V> {
+    fn get(&mut self, key: &K) -> Option<&V> {
+        let idx = *self.key_map.get(key)?;
+        match self.slab.list_of(idx) {
+            T1 | T2 => self.slab.move_to_front(T2, idx),
+            _ => return None, // ghost: adaptation happens when the caller puts it back
+        }
+        Some(self.slab.get(idx).value())
+    }
+
+    fn put(&mut self, key: K, value: V) -> Vec<(K, V)> {
+        if self.capacity == 0 {
+            return vec![(key, value)];
+        }
+        let c = self.capacity;
+        let mut evicted = Vec::new();
+        if let Some(&idx) = self.key_map.get(&key) {
+            let list = self.slab.list_of(idx);
+            if list == T1 || list == T2 {
+                self.slab.get_mut(idx).value = Some(value);
+                self.slab.move_to_front(T2, idx);
+                return evicted;
+            }
+            // Ghost hit: adapt p towards the list that would have kept the key
+            let (b1, b2) = (self.slab.list_len(B1), self.slab.list_len(B2));
+            if list == B1 {
+                self.p = (self.p + (b2 / b1).max(1)).min(c);
+            } else {
+                self.p = self.p.saturating_sub((b1 / b2).max(1));
+            }
+            if self.resident_len() >= c {
+                evicted.push(self.replace(list == B2));
+            }
+            self.slab.get_mut(idx).value = Some(value);
+            self.slab.move_to_front(T2, idx);
+            return evicted;
+        }
+
+        // Brand new key
+        let l1 = self.slab.list_len(T1) + self.slab.list_len(B1);
+        let total = l1 + self.slab.list_len(T2) + self.slab.list_len(B2);
+        if l1 >= c {
+            if self.slab.list_len(T1) < c {
+                self.drop_ghost(B1);
+                evicted.push(self.replace(false));
+            } else {
+                // B1 is empty and T1 fills the cache: evict T1's LRU outright
+                let tail = self.slab.back(T1).expect("T1 should not be empty");
+                let victim = self.slab.remove(tail);
+                self.key_map.remove(&victim.key);
+                evicted.push((victim.key, victim.value.expect("resident slot")));
+            }
+        } else if total >= c {
+            if total >= 2 * c {
+                self.drop_ghost(B2);
+            }
+            if self.resident_len() >= c {
+                evicted.push(self.replace(false));
+            }
+        }
+        let idx = self.slab.push_front(T1, Slot::resident(key.clone(), value));
+        self.key_map.insert(key, idx);
+        evicted
+    }
+
+    fn len(&self) -> usize {
+        self.resident_len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn name(&self) -> &'static str {
+        "ARC"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_evicts_least_recent() {
+        let mut cache = LruCache::new(2);
+        cache.put(1, 10);
+        cache.put(2, 20);
+        cache.get(&1);
+        assert_eq!(cache.put(3, 30), vec![(2, 20)]);
+        assert_eq!(cache.get(&1), Some(&10));
+        assert_eq!(cache.len(), 2);
+    }
+
+    // A one-hit scan only churns A1in; keys promoted to Am survive it
+    #[test]
+    fn test_two_queue_resists_scan() {
+        let mut cache = TwoQueueCache::new(8);
+        for key in 0..4 {
+            cache.put(key, key);
+        }
+        // Push 0..4 out of A1in into the ghost list, then bring them back into Am
+        for key in 100..108 {
+            cache.put(key, key);
+        }
+        for key in 0..4 {
+            cache.put(key, key);
+        }
+        for key in 1000..1100 {
+            cache.put(key, key);
+            assert!(cache.len() <= 8);
+        }
+        for key in 0..4 {
+            assert_eq!(cache.get(&key), Some(&key));
+        }
+    }
+
+    #[test]
+    fn test_two_queue_ghost_hit_is_a_miss() {
+        let mut cache = TwoQueueCache::new(4);
+        for key in 0..5 {
+            cache.put(key, key);
+        }
+        // 0 left A1in (capacity 1) as a ghost: remembered, not stored
+        assert_eq!(cache.get(&0), None);
+        assert_eq!(cache.len(), 4);
+    }
+
+    #[test]
+    fn test_arc_ghost_hits_move_target() {
+        let mut cache = ArcCache::new(4);
+        for key in 0..4 {
+            cache.put(key, key);
+        }
+        cache.get(&3); // T1 = [2, 1, 0], T2 = [3]
+        assert_eq!(cache.put(4, 4), vec![(0, 0)]); // 0 -> ghost in B1
+        assert_eq!(cache.get(&0), None);
+        assert_eq!(cache.put(0, 0), vec![(1, 1)]); // B1 ghost hit: favour recency
+        assert_eq!(cache.target_recent(), 1);
+        assert_eq!(cache.get(&0), Some(&0));
+        assert_eq!(cache.len(), 4);
+    }
+
+    #[test]
+    fn test_arc_frequent_keys_survive_scan() {
+        let mut cache = ArcCache::new(4);
+        cache.put(-1, 1);
+        cache.put(-2, 2);
+        cache.get(&-1);
+        cache.get(&-2); // T2 = [-2, -1]
+        for key in 0..100 {
+            cache.put(key, key);
+        }
+        assert_eq!(cache.get(&-1), Some(&1));
+        assert_eq!(cache.get(&-2), Some(&2));
+    }
+
+    // Every policy honours its capacity and returns what it evicts
+    #[test]
+    fn test_all_policies_through_trait() {
+        for policy in Policy::ALL {
+            let mut cache: Box<dyn Cache<u32, u32>> = policy.build(16);
+            let mut evicted = 0;
+            for i in 0..1000u32 {
+                let key = (i * 7919) % 64;
+                if cache.get(&key).is_none() {
+                    evicted += cache.put(key, key).len();
+                }
+                assert!(cache.len() <= 16, "{} over capacity", cache.name());
+                if let Some(v) = cache.get(&key) {
+                    assert_eq!(*v, key);
+                }
+            }
+            assert!(evicted > 0, "{} never evicted", cache.name());
+
+            let mut empty: Box<dyn Cache<u32, u32>> = policy.build(0);
+            assert_eq!(empty.put(1, 1), vec![(1, 1)]);
+            assert!(empty.is_empty());
+        }
+    }
+}
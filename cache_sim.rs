@@ -0,0 +1,433 @@
+// Trace-driven cache simulator.
+//
+// Replays an access trace against every eviction policy in cache_policy.rs
+// (LFUCache included) at several capacities and prints hit ratio, evictions
+// and throughput side by side.
+//
+//   rustc -O cache_sim.rs && ./cache_sim trace.txt --capacities 100,1000
+//   ./cache_sim requests.jsonl --json-key request_id --policies lfu,arc
+//
+// Trace formats, detected per line:
+//   plain      one key per line                      user:42
+//   JSON lines one object per line, key in a field   {"key": "user:42", ...}
+//
+// Every access is "get; on a miss, put" - the usual read-through pattern.
+
+// lfu.rs is also a standalone program; its main() and demo-only items are unused here
+#[allow(dead_code)]
+#[path = "lfu.rs"]
+pub mod lfu;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::process;
+use std::time::Instant;
+
+use lfu::cache_policy::{Cache, Policy};
+
+struct Options {
+    trace_path: String,
+    json_key: String,
+    capacities: Vec<usize>,
+    policies: Vec<Policy>,
+}
+
+const USAGE: &str = "usage: cache_sim <trace> [--json-key FIELD] [--capacities N,N,...] [--policies lru,lfu,arc,2q]";
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        trace_path: String::new(),
+        json_key: "key".to_string(),
+        capacities: Vec::new(),
+        policies: Policy::ALL.to_vec(),
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--json-key" => options.json_key = value()?.clone(),
+            "--capacities" => {
+                options.capacities = value()?
+                    .split(',')
+                    .map(|n| n.trim().parse().map_err(|_| format!("bad capacity '{}'", n)))
+                    .collect::<Result<_, _>>()?;
+            }
+            "--policies" => {
+                options.policies = value()?
+                    .split(',')
+                    .map(|name| name.trim().parse())
+                    .collect::<Result<_, _>>()?;
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            path if options.trace_path.is_empty() => options.trace_path = path.to_string(),
+            extra => return Err(format!("unexpected argument '{}'", extra)),
+        }
+    }
+    if options.trace_path.is_empty() {
+        return Err("missing trace file".to_string());
+    }
+    Ok(options)
+}
+
+// Minimal reader for one JSON object per line: enough to pull out one
+// top-level string or number field without a JSON dependency.
+// `pos` is a byte offset that always sits on a char boundary.
+struct JsonCursor<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> JsonCursor<'a> {
+    fn peek(&mut self) -> Option<u8> {
+        let bytes = self.text.as_bytes();
+        while self.pos < bytes.len() && bytes[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+        bytes.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        if self.peek()? == byte {
+            self.pos += 1;
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.expect(b'"')?;
+        let mut out = String::new();
+        let start = self.pos;
+        let mut chars = self.text[start..].char_indices();
+        loop {
+            let (offset, ch) = chars.next()?;
+            match ch {
+                '"' => {
+                    self.pos = start + offset + 1;
+                    return Some(out);
+                }
+                '\\' => {
+                    let (escape_at, escape) = chars.next()?;
+                    out.push(match escape {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'u' => {
+                            let hex_at = start + escape_at + 1;
+                            let code = self.hex4(hex_at)?;
+                            // Four hex digits are four one-byte chars
+                            chars.nth(3)?;
+                            match code {
+                                // A high surrogate must be followed by a \u low surrogate;
+                                // the pair is one char outside the Basic Multilingual Plane
+                                0xD800..=0xDBFF => {
+                                    if self.text.get(hex_at + 4..hex_at + 6)? != "\\u" {
+                                        return None;
+                                    }
+                                    let low = self.hex4(hex_at + 6)?;
+                                    if !(0xDC00..=0xDFFF).contains(&low) {
+                                        return None;
+                                    }
+                                    chars.nth(5)?;
+                                    char::from_u32(0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00))?
+                                }
+                                // A low surrogate with no high one before it
+                                0xDC00..=0xDFFF => return None,
+                                _ => char::from_u32(code)?,
+                            }
+                        }
+                        other => other, // \" \\ \/
+                    });
+                }
+                _ => out.push(ch),
+            }
+        }
+    }
+
+    // Helper: The code unit spelled by the four hex digits at byte `at`.
+    // from_str_radix alone would also take a sign, as in \u+041.
+    fn hex4(&self, at: usize) -> Option<u32> {
+        let digits = self.text.get(at..at + 4)?;
+        if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        u32::from_str_radix(digits, 16).ok()
+    }
+
+    // Skip any value; returns the raw text of scalars (numbers, true, false, null)
+    fn skip_value(&mut self) -> Option<&'a str> {
+        match self.peek()? {
+            b'"' => {
+                self.string()?;
+                Some("")
+            }
+            open @ (b'{' | b'[') => {
+                let close = if open == b'{' { b'}' } else { b']' };
+                self.pos += 1;
+                if self.peek()? == close {
+                    self.pos += 1;
+                    return Some("");
+                }
+                loop {
+                    if open == b'{' {
+                        self.string()?;
+                        self.expect(b':')?;
+                    }
+                    self.skip_value()?;
+                    match self.peek()? {
+                        b',' => self.pos += 1,
+                        byte if byte == close => {
+                            self.pos += 1;
+                            return Some("");
+                        }
+                        _ => return None,
+                    }
+                }
+            }
+            _ => {
+                let start = self.pos;
+                let bytes = self.text.as_bytes();
+                while self.pos < bytes.len() && !b",}] \t\r\n".contains(&bytes[self.pos]) {
+                    self.pos += 1;
+                }
+                self.text.get(start..self.pos)
+            }
+        }
+    }
+}
+
+/// Value of top-level `field` in a one-line JSON object, if it is a string or a number.
+fn json_field(line: &str, field: &str) -> Option<String> {
+    let mut cursor = JsonCursor { text: line, pos: 0 };
+    cursor.expect(b'{')?;
+    if cursor.peek()? == b'}' {
+        return None;
+    }
+    loop {
+        let name = cursor.string()?;
+        cursor.expect(b':')?;
+        if name == field {
+            return match cursor.peek()? {
+                b'"' => cursor.string(),
+                b'{' | b'[' => None,
+                _ => cursor.skip_value().map(str::to_string),
+            };
+        }
+        cursor.skip_value()?;
+        match cursor.peek()? {
+            b',' => cursor.pos += 1,
+            _ => return None,
+        }
+    }
+}
+
+/// Key for one trace line: the JSON field for objects, the trimmed line otherwise.
+/// None for blank lines and objects without the field.
+fn trace_key(line: &str, json_key: &str) -> Option<String> {
+    let line = line.trim();
+    if line.is_empty() {
+        None
+    } else if line.starts_with('{') {
+        json_field(line, json_key)
+    } else {
+        Some(line.to_string())
+    }
+}
+
+struct Trace {
+    accesses: Vec<u32>, // keys interned to dense ids so hashing cost is the same for every policy
+    distinct: usize,
+    skipped: usize,
+}
+
+fn load_trace(path: &str, json_key: &str) -> io::Result<Trace> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut ids: HashMap<String, u32> = HashMap::new();
+    let mut accesses = Vec::new();
+    let mut skipped = 0;
+    for line in reader.lines() {
+        match trace_key(&line?, json_key) {
+            Some(key) => {
+                let next_id = ids.len() as u32;
+                accesses.push(*ids.entry(key).or_insert(next_id));
+            }
+            None => skipped += 1,
+        }
+    }
+    Ok(Trace {
+        accesses,
+        distinct: ids.len(),
+        skipped,
+    })
+}
+
+struct RunStats {
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+    seconds: f64,
+}
+
+fn simulate(cache: &mut dyn Cache<u32, ()>, accesses: &[u32]) -> RunStats {
+    let mut stats = RunStats {
+        hits: 0,
+        misses: 0,
+        evictions: 0,
+        seconds: 0.0,
+    };
+    let start = Instant::now();
+    for &key in accesses {
+        if cache.get(&key).is_some() {
+            stats.hits += 1;
+        } else {
+            stats.misses += 1;
+            stats.evictions += cache.put(key, ()).len() as u64;
+        }
+    }
+    stats.seconds = start.elapsed().as_secs_f64();
+    stats
+}
+
+// 1%, 5%, 10%, 25% and 50% of the distinct keys, at least 1
+fn default_capacities(distinct: usize) -> Vec<usize> {
+    let mut capacities: Vec<usize> = [1, 5, 10, 25, 50]
+        .iter()
+        .map(|pct| (distinct * pct / 100).max(1))
+        .collect();
+    capacities.dedup();
+    capacities
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Error: {}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    let trace = match load_trace(&options.trace_path, &options.json_key) {
+        Ok(trace) => trace,
+        Err(e) => {
+            eprintln!("Error: cannot read {}: {}", options.trace_path, e);
+            process::exit(1);
+        }
+    };
+    let capacities = if options.capacities.is_empty() {
+        default_capacities(trace.distinct)
+    } else {
+        options.capacities.clone()
+    };
+
+    println!(
+        "trace {}: {} accesses, {} distinct keys, {} lines skipped",
+        options.trace_path,
+        trace.accesses.len(),
+        trace.distinct,
+        trace.skipped
+    );
+    println!(
+        "{:<8} {:>10} {:>12} {:>12} {:>9} {:>12} {:>14}",
+        "policy", "capacity", "hits", "misses", "hit %", "evictions", "ops/sec"
+    );
+    for &capacity in &capacities {
+        for &policy in &options.policies {
+            let mut cache = policy.build::<u32, ()>(capacity);
+            let run = simulate(cache.as_mut(), &trace.accesses);
+            let lookups = (run.hits + run.misses).max(1);
+            println!(
+                "{:<8} {:>10} {:>12} {:>12} {:>8.2}% {:>12} {:>14.0}",
+                cache.name(),
+                capacity,
+                run.hits,
+                run.misses,
+                100.0 * run.hits as f64 / lookups as f64,
+                run.evictions,
+                trace.accesses.len() as f64 / run.seconds.max(1e-9)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_field_reads_strings_and_numbers() {
+        let line = r#"{"request_id": "user-001", "n": 42, "title": "say \"hi\"\n"}"#;
+        assert_eq!(json_field(line, "request_id"), Some("user-001".to_string()));
+        assert_eq!(json_field(line, "n"), Some("42".to_string()));
+        assert_eq!(json_field(line, "title"), Some("say \"hi\"\n".to_string()));
+        assert_eq!(json_field(line, "missing"), None);
+    }
+
+    // Field names inside nested values must not be mistaken for top-level ones
+    #[test]
+    fn test_json_field_skips_nested_values() {
+        let line = r#"{"meta": {"key": "inner", "list": [1, {"key": 2}]}, "tags": [], "key": "outer"}"#;
+        assert_eq!(json_field(line, "key"), Some("outer".to_string()));
+        assert_eq!(json_field(r#"{"key": {"a": 1}}"#, "key"), None);
+        assert_eq!(json_field(r#"{"key": "été"}"#, "key"), Some("été".to_string()));
+    }
+
+    #[test]
+    fn test_json_field_unicode_escapes_and_long_strings() {
+        let line = r#"{"note": "caf\u00e9 \u00e9t\u00e9", "key": "k"}"#;
+        assert_eq!(json_field(line, "note"), Some("café été".to_string()));
+        assert_eq!(json_field(r#"{"key": "\u00e"}"#, "key"), None);
+        assert_eq!(json_field(r#"{"key": "\u+041"}"#, "key"), None);
+        // Surrogate pairs decode to one char each, so distinct emoji stay distinct keys
+        assert_eq!(json_field(r#"{"key": "\uD83D\uDE00"}"#, "key"), Some("\u{1F600}".to_string()));
+        assert_eq!(json_field(r#"{"key": "\uD83D\uDE01"}"#, "key"), Some("\u{1F601}".to_string()));
+        assert_eq!(json_field(r#"{"key": "\uD83D"}"#, "key"), None);
+        assert_eq!(json_field(r#"{"key": "\uD83Dx\uDE00"}"#, "key"), None);
+        assert_eq!(json_field(r#"{"key": "\uD83D\u0041"}"#, "key"), None);
+        assert_eq!(json_field(r#"{"key": "\uDE00"}"#, "key"), None);
+        // One pass per string: a long line is not re-validated at every char
+        let long = format!(r#"{{"blob": "{}", "key": "k"}}"#, "é".repeat(200_000));
+        assert_eq!(json_field(&long, "key"), Some("k".to_string()));
+    }
+
+    #[test]
+    fn test_trace_key_formats() {
+        assert_eq!(trace_key("  user:42 \n", "key"), Some("user:42".to_string()));
+        assert_eq!(trace_key(r#"{"key": "user:42"}"#, "key"), Some("user:42".to_string()));
+        assert_eq!(trace_key("   ", "key"), None);
+        assert_eq!(trace_key(r#"{"id": 1}"#, "key"), None);
+    }
+
+    // a b a c a b with 2 slots under LRU: hits on the 3rd and 5th access only
+    #[test]
+    fn test_simulate_counts_hits_and_evictions() {
+        let mut cache = Policy::Lru.build::<u32, ()>(2);
+        let run = simulate(cache.as_mut(), &[0, 1, 0, 2, 0, 1]);
+        assert_eq!((run.hits, run.misses, run.evictions), (2, 4, 2));
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args: Vec<String> = ["t.txt", "--capacities", "10,20", "--policies", "lfu,2q"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let options = parse_args(&args).unwrap();
+        assert_eq!(options.trace_path, "t.txt");
+        assert_eq!(options.capacities, vec![10, 20]);
+        assert_eq!(options.policies, vec![Policy::Lfu, Policy::TwoQueue]);
+
+        assert!(parse_args(&[]).is_err());
+        assert!(parse_args(&["t".to_string(), "--policies".to_string(), "mru".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_default_capacities() {
+        assert_eq!(default_capacities(1000), vec![10, 50, 100, 250, 500]);
+        assert_eq!(default_capacities(3), vec![1]);
+    }
+}
@@ -0,0 +1,543 @@
+// Single-storage bidirectional map, and a memory comparison against BiMap.
+//
+// BiMap keeps a HashMap in each direction, so every left and right value is
+// cloned and stored twice. CompactBiMap stores each pair once in an arena
+// (a Vec of slots) and indexes the arena from both sides with open-addressing
+// tables of 4-byte slot numbers. A probe hashes the query and compares it
+// against the pair the slot number points at, so neither index holds a key.
+//
+//   rustc -O compact_bimap.rs && ./compact_bimap [pairs]
+//
+// main builds the same host name <-> IPv4 workload into both maps and reports
+// the heap bytes each one retains, counted by a wrapper around the system
+// allocator.
+
+// bidirectional_map.rs is also a standalone program; its main() is unused here
+#[allow(dead_code)]
+#[path = "bidirectional_map.rs"]
+pub mod bidirectional_map;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bidirectional_map::{BiMap, OnConflict, RightTaken};
+
+const USAGE: &str = "usage: compact_bimap [pairs]";
+
+// Marks an empty bucket; also why the arena is capped below u32::MAX slots
+const EMPTY: u32 = u32::MAX;
+
+// Open-addressing (linear probing) table of arena slot numbers for one side
+struct SlotIndex {
+    buckets: Vec<u32>,
+    len: usize,
+}
+
+impl SlotIndex {
+    fn new() -> Self {
+        SlotIndex { buckets: Vec::new(), len: 0 }
+    }
+
+    // Bucket holding a slot for which is_match is true, probing from the hash's home bucket
+    fn find<F: Fn(u32) -> bool>(&self, hash: u64, is_match: F) -> Option<usize> {
+        if self.buckets.is_empty() {
+            return None;
+        }
+        let mask = self.buckets.len() - 1;
+        let mut pos = hash as usize & mask;
+        // Load stays below 3/4, so an empty bucket always ends the probe
+        loop {
+            let slot = self.buckets[pos];
+            if slot == EMPTY {
+                return None;
+            }
+            if is_match(slot) {
+                return Some(pos);
+            }
+            pos = (pos + 1) & mask;
+        }
+    }
+
+    // The caller guarantees the key is not indexed yet; hash_of rehashes existing slots on growth
+    fn insert<F: Fn(u32) -> u64>(&mut self, hash: u64, slot: u32, hash_of: F) {
+        if (self.len + 1) * 4 > self.buckets.len() * 3 {
+            let grown = vec![EMPTY; (self.buckets.len() * 2).max(8)];
+            let old = std::mem::replace(&mut self.buckets, grown);
+            for moved in old.into_iter().filter(|&moved| moved != EMPTY) {
+                self.place(hash_of(moved), moved);
+            }
+        }
+        self.place(hash, slot);
+        self.len += 1;
+    }
+
+    fn place(&mut self, hash: u64, slot: u32) {
+        let mask = self.buckets.len() - 1;
+        let mut pos = hash as usize & mask;
+        while self.buckets[pos] != EMPTY {
+            pos = (pos + 1) & mask;
+        }
+        self.buckets[pos] = slot;
+    }
+
+    // Backward-shift deletion: later entries of the probe run move up into the
+    // hole, so lookups never need tombstones.
+    fn remove_at<F: Fn(u32) -> u64>(&mut self, pos: usize, hash_of: F) {
+        let mask = self.buckets.len() - 1;
+        let mut hole = pos;
+        let mut next = (pos + 1) & mask;
+        loop {
+            let slot = self.buckets[next];
+            if slot == EMPTY {
+                break;
+            }
+            let home = hash_of(slot) as usize & mask;
+            // Movable only if its home bucket is at or before the hole (cyclically)
+            if next.wrapping_sub(home) & mask >= next.wrapping_sub(hole) & mask {
+                self.buckets[hole] = slot;
+                hole = next;
+            }
+            next = (next + 1) & mask;
+        }
+        self.buckets[hole] = EMPTY;
+        self.len -= 1;
+    }
+
+    fn clear(&mut self) {
+        self.buckets.clear();
+        self.len = 0;
+    }
+}
+
+fn pair_of<L, R>(slots: &[Option<(L, R)>], slot: u32) -> &(L, R) {
+    slots[slot as usize].as_ref().expect("indexed slot is occupied")
+}
+
+/// Bidirectional Map that stores each pair once.
+/// Same behavior as BiMap (including OnConflict), but values need no Clone: the
+/// pair lives in `slots` and both indexes refer to it by slot number.
+/// Edge Case: Removed pairs leave a hole in the arena -> the slot is reused by the next put.
+pub struct CompactBiMap<L, R> {
+    slots: Vec<Option<(L, R)>>,
+    free: Vec<u32>,
+    by_left: SlotIndex,
+    by_right: SlotIndex,
+    hasher: RandomState,
+    on_conflict: OnConflict,
+}
+
+impl<L: Hash + Eq, R: Hash + Eq> Default for CompactBiMap<L, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<L: Hash + Eq, R: Hash + Eq> CompactBiMap<L, R> {
+    pub fn new() -> Self {
+        CompactBiMap {
+            slots: Vec::new(),
+            free: Vec::new(),
+            by_left: SlotIndex::new(),
+            by_right: SlotIndex::new(),
+            hasher: RandomState::new(),
+            on_conflict: OnConflict::Overwrite,
+        }
+    }
+
+    /// Choose how put() handles a right value already bound elsewhere (default Overwrite).
+    pub fn with_conflict_policy(mut self, on_conflict: OnConflict) -> Self {
+        self.on_conflict = on_conflict;
+        self
+    }
+
+    /// Inserts a left-right pair; conflicts on `right` follow on_conflict, like BiMap::put.
+    /// Edge Case: Left already bound -> its old pair is removed, freeing the old right.
+    pub fn put(&mut self, left: L, right: R) -> Result<(), RightTaken<L, R>> {
+        if let Some(slot) = self.right_slot(&right) {
+            if pair_of(&self.slots, slot).0 == left {
+                // The exact pair is already stored
+                return Ok(());
+            }
+            match self.on_conflict {
+                OnConflict::Reject => return Err(RightTaken { left, right }),
+                OnConflict::KeepExisting => return Ok(()),
+                OnConflict::Overwrite => {
+                    self.remove_slot(slot);
+                }
+            }
+        }
+        if let Some(slot) = self.left_slot(&left) {
+            self.remove_slot(slot);
+        }
+        self.insert_pair(left, right);
+        Ok(())
+    }
+
+    pub fn get_by_left<Q>(&self, left: &Q) -> Option<&R>
+    where
+        L: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.left_slot(left).map(|slot| &pair_of(&self.slots, slot).1)
+    }
+
+    pub fn get_by_right<Q>(&self, right: &Q) -> Option<&L>
+    where
+        R: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.right_slot(right).map(|slot| &pair_of(&self.slots, slot).0)
+    }
+
+    pub fn contains_left<Q>(&self, left: &Q) -> bool
+    where
+        L: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.left_slot(left).is_some()
+    }
+
+    pub fn contains_right<Q>(&self, right: &Q) -> bool
+    where
+        R: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.right_slot(right).is_some()
+    }
+
+    pub fn remove_by_left<Q>(&mut self, left: &Q) -> Option<R>
+    where
+        L: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let slot = self.left_slot(left)?;
+        Some(self.remove_slot(slot).1)
+    }
+
+    pub fn remove_by_right<Q>(&mut self, right: &Q) -> Option<L>
+    where
+        R: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let slot = self.right_slot(right)?;
+        Some(self.remove_slot(slot).0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_left.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// All pairs as (left, right), in arena order.
+    pub fn iter(&self) -> impl Iterator<Item = (&L, &R)> + '_ {
+        self.slots.iter().filter_map(|slot| slot.as_ref().map(|(left, right)| (left, right)))
+    }
+
+    /// Removes and yields every pair; the map is empty (and unallocated) right away.
+    pub fn drain(&mut self) -> impl Iterator<Item = (L, R)> {
+        self.by_left.clear();
+        self.by_right.clear();
+        self.free.clear();
+        std::mem::take(&mut self.slots).into_iter().flatten()
+    }
+
+    /// Keeps only the pairs for which `keep(left, right)` is true.
+    pub fn retain<F: FnMut(&L, &R) -> bool>(&mut self, mut keep: F) {
+        for slot in 0..self.slots.len() as u32 {
+            let drop_it = match self.slots[slot as usize] {
+                Some((ref left, ref right)) => !keep(left, right),
+                None => false,
+            };
+            if drop_it {
+                self.remove_slot(slot);
+            }
+        }
+    }
+
+    fn left_slot<Q>(&self, left: &Q) -> Option<u32>
+    where
+        L: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let slots = &self.slots;
+        let pos = self.by_left.find(self.hasher.hash_one(left), |slot| pair_of(slots, slot).0.borrow() == left)?;
+        Some(self.by_left.buckets[pos])
+    }
+
+    fn right_slot<Q>(&self, right: &Q) -> Option<u32>
+    where
+        R: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let slots = &self.slots;
+        let pos = self.by_right.find(self.hasher.hash_one(right), |slot| pair_of(slots, slot).1.borrow() == right)?;
+        Some(self.by_right.buckets[pos])
+    }
+
+    fn insert_pair(&mut self, left: L, right: R) {
+        let left_hash = self.hasher.hash_one(&left);
+        let right_hash = self.hasher.hash_one(&right);
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.slots[slot as usize] = Some((left, right));
+                slot
+            }
+            None => {
+                assert!(self.slots.len() < EMPTY as usize, "CompactBiMap is full");
+                self.slots.push(Some((left, right)));
+                (self.slots.len() - 1) as u32
+            }
+        };
+        let slots = &self.slots;
+        let hasher = &self.hasher;
+        self.by_left.insert(left_hash, slot, |moved| hasher.hash_one(&pair_of(slots, moved).0));
+        self.by_right.insert(right_hash, slot, |moved| hasher.hash_one(&pair_of(slots, moved).1));
+    }
+
+    // Unindexes the pair from both sides, then frees its slot
+    fn remove_slot(&mut self, slot: u32) -> (L, R) {
+        let slots = &self.slots;
+        let hasher = &self.hasher;
+        let (left, right) = pair_of(slots, slot);
+        let left_pos = self.by_left.find(hasher.hash_one(left), |found| found == slot);
+        let right_pos = self.by_right.find(hasher.hash_one(right), |found| found == slot);
+        self.by_left.remove_at(left_pos.expect("pair indexed by left"), |moved| {
+            hasher.hash_one(&pair_of(slots, moved).0)
+        });
+        self.by_right.remove_at(right_pos.expect("pair indexed by right"), |moved| {
+            hasher.hash_one(&pair_of(slots, moved).1)
+        });
+        self.free.push(slot);
+        self.slots[slot as usize].take().expect("indexed slot is occupied")
+    }
+}
+
+// System allocator that tracks how many bytes are currently allocated
+struct CountingAlloc;
+
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            LIVE_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            LIVE_BYTES.fetch_add(new_size, Ordering::Relaxed);
+            LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+// Pair i of the workload: a unique host name and a unique IPv4 address string
+fn host_pair(i: usize) -> (String, String) {
+    (
+        format!("host-{:07}.example.com", i),
+        format!("10.{}.{}.{}", (i >> 16) & 0xFF, (i >> 8) & 0xFF, i & 0xFF),
+    )
+}
+
+// Builds a map and returns it with the heap bytes it retains (strings included)
+fn retained<M, F: FnOnce() -> M>(build: F) -> (M, usize) {
+    let before = LIVE_BYTES.load(Ordering::Relaxed);
+    let map = build();
+    (map, LIVE_BYTES.load(Ordering::Relaxed) - before)
+}
+
+fn main() {
+    let pairs = match std::env::args().nth(1).map(|arg| arg.parse::<usize>()) {
+        None => 1_000_000,
+        // The workload's IPs are unique below 2^24
+        Some(Ok(pairs)) if pairs > 0 && pairs <= 1 << 24 => pairs,
+        Some(_) => {
+            eprintln!("Error: pairs must be between 1 and {}\n{}", 1 << 24, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let (two_maps, two_maps_bytes) = retained(|| {
+        let mut map = BiMap::new();
+        for i in 0..pairs {
+            let (host, ip) = host_pair(i);
+            let _ = map.put(host, ip);
+        }
+        map
+    });
+    let (compact, compact_bytes) = retained(|| {
+        let mut map = CompactBiMap::new();
+        for i in 0..pairs {
+            let (host, ip) = host_pair(i);
+            let _ = map.put(host, ip);
+        }
+        map
+    });
+
+    // Same contents, or the comparison means nothing
+    assert_eq!(two_maps.len(), compact.len());
+    for i in (0..pairs).step_by((pairs / 1000).max(1)) {
+        let (host, ip) = host_pair(i);
+        assert_eq!(compact.get_by_left(&host), two_maps.get_by_left(&host));
+        assert_eq!(compact.get_by_right(&ip), two_maps.get_by_right(&ip));
+    }
+
+    const MIB: f64 = 1024.0 * 1024.0;
+    println!("{} pairs (host name <-> IPv4 string), heap bytes retained", pairs);
+    println!("{:<14} {:>12} {:>12}", "layout", "MiB", "bytes/pair");
+    for &(name, bytes) in &[("BiMap", two_maps_bytes), ("CompactBiMap", compact_bytes)] {
+        println!("{:<14} {:>12.1} {:>12.1}", name, bytes as f64 / MIB, bytes as f64 / pairs as f64);
+    }
+    let saved = two_maps_bytes.saturating_sub(compact_bytes);
+    println!(
+        "saved {:.1} MiB ({:.1}%)",
+        saved as f64 / MIB,
+        100.0 * saved as f64 / two_maps_bytes as f64
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bidirectional_map::tests::Rng;
+
+    // Both indexes must hold exactly the occupied slots, each findable by its own key
+    fn assert_consistent<L: Hash + Eq, R: Hash + Eq>(map: &CompactBiMap<L, R>) {
+        let occupied = map.slots.iter().filter(|slot| slot.is_some()).count();
+        assert_eq!(map.by_left.len, occupied);
+        assert_eq!(map.by_right.len, occupied);
+        assert_eq!(map.free.len(), map.slots.len() - occupied);
+        for (slot, pair) in map.slots.iter().enumerate() {
+            if let Some((ref left, ref right)) = *pair {
+                assert_eq!(map.left_slot(left), Some(slot as u32));
+                assert_eq!(map.right_slot(right), Some(slot as u32));
+            }
+        }
+    }
+
+    #[test]
+    fn test_lookups_and_policies() {
+        for policy in [OnConflict::Overwrite, OnConflict::Reject, OnConflict::KeepExisting] {
+            let mut map = CompactBiMap::new().with_conflict_policy(policy);
+            map.put("google.com", "1.2.3.4").unwrap();
+            assert_eq!(map.get_by_left("google.com"), Some(&"1.2.3.4"));
+            assert_eq!(map.get_by_right("1.2.3.4"), Some(&"google.com"));
+            let result = map.put("mirror.com", "1.2.3.4");
+            let holder = map.get_by_right("1.2.3.4").copied();
+            match policy {
+                OnConflict::Overwrite => {
+                    assert_eq!(result, Ok(()));
+                    assert_eq!(holder, Some("mirror.com"));
+                    assert!(!map.contains_left("google.com"));
+                }
+                OnConflict::Reject => {
+                    assert_eq!(result, Err(RightTaken { left: "mirror.com", right: "1.2.3.4" }));
+                    assert_eq!(holder, Some("google.com"));
+                }
+                OnConflict::KeepExisting => {
+                    assert_eq!(result, Ok(()));
+                    assert_eq!(holder, Some("google.com"));
+                }
+            }
+            assert_eq!(map.len(), 1);
+            assert_consistent(&map);
+        }
+    }
+
+    // Each pair is stored once: no Clone bound, and the arena holds one slot per pair
+    #[test]
+    fn test_pairs_stored_once_and_slots_reused() {
+        #[derive(Debug, PartialEq, Eq, Hash)]
+        struct NoClone(u32);
+
+        let mut map = CompactBiMap::new();
+        for n in 0..100 {
+            map.put(n, NoClone(n * 10)).unwrap();
+        }
+        assert_eq!(map.slots.len(), 100);
+        assert_eq!(map.remove_by_right(&NoClone(500)), Some(50));
+        assert_eq!(map.remove_by_left(&7).map(|right| right.0), Some(70));
+        map.put(1000, NoClone(1)).unwrap();
+        map.put(1001, NoClone(2)).unwrap();
+        // The two freed slots were reused
+        assert_eq!(map.slots.len(), 100);
+        assert_eq!(map.get_by_right(&NoClone(2)), Some(&1001));
+        assert_consistent(&map);
+    }
+
+    #[test]
+    fn test_iter_retain_and_drain() {
+        let mut map = CompactBiMap::new();
+        for n in 0..10u32 {
+            map.put(n, format!("host-{}", n)).unwrap();
+        }
+        assert_eq!(map.iter().count(), 10);
+        map.retain(|left, _| left % 2 == 0);
+        assert_eq!(map.len(), 5);
+        assert!(!map.contains_right("host-3"));
+        assert_eq!(map.get_by_right("host-4"), Some(&4));
+        assert_consistent(&map);
+
+        let mut drained: Vec<_> = map.drain().collect();
+        drained.sort();
+        assert_eq!(drained.len(), 5);
+        assert_eq!(drained[0], (0, "host-0".to_string()));
+        assert!(map.is_empty());
+        assert_eq!(map.get_by_left(&0), None);
+        map.put(1, "again".to_string()).unwrap();
+        assert_consistent(&map);
+    }
+
+    // Property: under every policy, any mix of puts, removals and retains leaves
+    // CompactBiMap answering exactly like BiMap. Small domains keep probe runs
+    // long and collisions frequent, which exercises backward-shift deletion.
+    #[test]
+    fn test_property_matches_bimap() {
+        for policy in [OnConflict::Overwrite, OnConflict::Reject, OnConflict::KeepExisting] {
+            for seed in 1..=100u64 {
+                let mut rng = Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+                let mut expected = BiMap::new().with_conflict_policy(policy);
+                let mut map = CompactBiMap::new().with_conflict_policy(policy);
+                for _ in 0..300 {
+                    let (left, right) = (rng.below(40), rng.below(40));
+                    match rng.below(6) {
+                        0 => assert_eq!(map.remove_by_left(&left), expected.remove_by_left(&left)),
+                        1 => assert_eq!(map.remove_by_right(&right), expected.remove_by_right(&right)),
+                        2 => {
+                            let cut = rng.below(16);
+                            map.retain(|l, r| (l + r) % 16 != cut);
+                            expected.retain(|l, r| (l + r) % 16 != cut);
+                        }
+                        _ => assert_eq!(map.put(left, right), expected.put(left, right), "{:?} seed {}", policy, seed),
+                    }
+                    assert_eq!(map.len(), expected.len(), "{:?} seed {}", policy, seed);
+                    for n in 0..40 {
+                        assert_eq!(map.get_by_left(&n), expected.get_by_left(&n), "{:?} seed {}", policy, seed);
+                        assert_eq!(map.get_by_right(&n), expected.get_by_right(&n), "{:?} seed {}", policy, seed);
+                    }
+                }
+                assert_consistent(&map);
+            }
+        }
+    }
+}
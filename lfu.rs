@@ -1,193 +1,1564 @@
-use std::collections::HashMap;
-struct Node
+use std::borrow::Borrow;
+use std::cmp::Reverse;
+#[cfg(test)]
+use std::collections::hash_map::DefaultHasher;
+use std::collections::hash_map::RandomState;
+use std::collections::{BinaryHeap, HashMap};
+use std::error::Error;
+use std::fmt::{self, Debug};
+use std::fs;
+#[cfg(test)]
+use std::hash::BuildHasherDefault;
+use std::hash::{BuildHasher, Hash};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub mod cache_policy;
+pub mod linked_slab;
+pub mod tiered_cache;
+
+use self::linked_slab::LinkedSlab;
+
+/// Source of "now" for TTL checks.
+/// Injected so tests can move time forward by hand instead of sleeping.
+pub trait Clock: Send {
+    fn now(&self) -> Instant;
+}
+
+/// Wall clock: Instant::now().
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Clock that only moves when advance() is called.
+/// Clones share the same time, so a test keeps one clone and hands the other to the cache.
+#[derive(Clone)]
+pub struct ManualClock {
+    start: Instant,
+    elapsed_nanos: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        ManualClock {
+            start: Instant::now(),
+            elapsed_nanos: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.elapsed_nanos.fetch_add(by.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + Duration::from_nanos(self.elapsed_nanos.load(Ordering::SeqCst))
+    }
+}
+
+// Computes an entry's weight (e.g. its size in bytes) for weighted caches
+type Weigher<K, V> = Box<dyn Fn(&K, &V) -> usize + Send>;
+
+/// Why an entry left the cache. Passed to the eviction listener.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionCause {
+    /// Picked by evict_lfu_node to make room (count or weight limit).
+    Capacity,
+    /// Old value overwritten by a put() on the same key.
+    Replaced,
+    /// TTL ran out.
+    Expired,
+    /// Taken out by remove().
+    Explicit,
+}
+
+/// try_put() found the cache full and every entry pinned, so nothing could be
+/// evicted to make room. Carries the pair that was not stored.
+#[derive(Debug, PartialEq, Eq)]
+pub struct AllPinned<K, V> {
+    pub key: K,
+    pub value: V,
+}
+
+impl<K, V> fmt::Display for AllPinned<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cache is full and every entry is pinned")
+    }
+}
+
+impl<K: Debug, V: Debug> Error for AllPinned<K, V> {}
+
+// store() and insert_new(): the entries handed back (evicted, or the refused pair
+// itself), and the stored node's index unless the value was refused
+type Inserted<K, V> = (Vec<(K, V)>, Option<usize>);
+
+// Called with every entry that leaves the cache, before it is dropped or returned
+type EvictionListener<K, V> = Box<dyn FnMut(&K, &V, EvictionCause) + Send>;
+
+// A per-process seed keeps callers from picking keys that collide in every row.
+// Tests use fixed keys instead, so which keys collide is the same on every run.
+#[cfg(not(test))]
+type SketchHasher = RandomState;
+#[cfg(test)]
+type SketchHasher = BuildHasherDefault<DefaultHasher>;
+
+/// Count-min sketch of recent access counts, used as a TinyLFU admission filter.
+/// Four rows of saturating 4-bit counters (kept in u8, capped at 15). After
+/// `sample_size` increments every counter is halved, so old popularity fades.
+struct FrequencySketch {
+    rows: [Vec<u8>; 4],
+    mask: u64,
+    additions: usize,
+    sample_size: usize,
+    hasher: SketchHasher,
+}
+
+impl FrequencySketch {
+    fn new(expected_entries: usize) -> Self {
+        // At least 256 wide: small caches still see many distinct keys, and a
+        // narrow sketch would inflate every estimate through collisions
+        let width = expected_entries.clamp(256, 1 << 16).next_power_of_two();
+        FrequencySketch {
+            rows: [vec![0; width], vec![0; width], vec![0; width], vec![0; width]],
+            mask: width as u64 - 1,
+            additions: 0,
+            sample_size: 10 * width,
+            hasher: SketchHasher::default(),
+        }
+    }
+
+    // Row i probes h1 + i * h2 (double hashing from one 64-bit hash)
+    fn slots<T: Hash + ?Sized>(&self, item: &T) -> [usize; 4] {
+        let hash = self.hasher.hash_one(item);
+        let h1 = hash & 0xffff_ffff;
+        let h2 = (hash >> 32) | 1;
+        [0u64, 1, 2, 3].map(|i| (h1.wrapping_add(i.wrapping_mul(h2)) & self.mask) as usize)
+    }
+
+    fn increment<T: Hash + ?Sized>(&mut self, item: &T) {
+        let slots = self.slots(item);
+        for (row, slot) in self.rows.iter_mut().zip(slots) {
+            if row[slot] < 15 {
+                row[slot] += 1;
+            }
+        }
+        self.additions += 1;
+        if self.additions >= self.sample_size {
+            self.reset();
+        }
+    }
+
+    fn estimate<T: Hash + ?Sized>(&self, item: &T) -> u8 {
+        let slots = self.slots(item);
+        self.rows.iter().zip(slots).map(|(row, slot)| row[slot]).min().unwrap_or(0)
+    }
+
+    // Halve every counter so the sketch tracks recent popularity, not all-time
+    fn reset(&mut self) {
+        for row in self.rows.iter_mut() {
+            for counter in row.iter_mut() {
+                *counter /= 2;
+            }
+        }
+        self.additions /= 2;
+    }
+}
+
+/// Snapshot returned by LFUCache::stats(). Counters run from creation or the last reset_stats().
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Puts that added a new key.
+    pub inserts: u64,
+    /// Puts that overwrote the value of a live key.
+    pub updates: u64,
+    /// Entries pushed out by evict_lfu_node to make room.
+    pub evictions: u64,
+    /// Entries dropped because their TTL ran out.
+    pub expirations: u64,
+    /// Entries taken out by remove().
+    pub removals: u64,
+    /// Puts refused: entry heavier than the budget, zero capacity, or admission filter.
+    pub rejections: u64,
+    pub len: usize,
+    pub total_weight: usize,
+    pub min_freq: usize,
+    /// (freq, number of entries in that frequency list), lowest freq first.
+    pub freq_histogram: Vec<(usize, usize)>,
+}
+
+impl CacheStats {
+    /// hits / (hits + misses); 0.0 before the first get().
+    pub fn hit_ratio(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+struct Node<K, V>
 {
-    key     : i32,
-    val     : i32,
+    key     : K,
+    val     : V,
     freq    : usize,
-    prev    : Option<usize>,
-    next    : Option<usize>,
+    expires_at : Option<Instant>, // None = lives until evicted
+    weight  : usize,              // 1 unless the cache is weighted
+    pinned  : bool,               // never evicted or expired while set
+}
+
+// All nodes sharing one freq: one slab list, head (MRU) to tail (LRU), and the
+// bucket shares the list's number. Buckets are themselves linked lowest freq
+// first, so moving a node to freq + 1 only looks at the next bucket and the LFU
+// victim is the first bucket's tail - no hashing involved.
+// A bucket is freed as soon as its last node leaves; it is never empty.
+struct Bucket {
+    freq: usize,
+    prev: Option<usize>,
+    next: Option<usize>,
 }
-pub struct LFUCache {
+
+pub struct LFUCache<K, V> {
     capacity: usize,
-    min_freq: usize,
-    key_map: HashMap<i32, usize>,
-    freq_heads: HashMap<usize, usize>,
-    freq_tails: HashMap<usize, usize>,
-    nodes: Vec<Node>,
+    key_map: HashMap<K, usize>,
+    // Every node, on the slab list of its frequency bucket
+    slab: LinkedSlab<Node<K, V>>,
+    // Indexed by slab list number; `first_bucket` has the lowest freq.
+    // Slots of freed lists are overwritten when add_list() hands the number out again.
+    buckets: Vec<Bucket>,
+    first_bucket: Option<usize>,
+    default_ttl: Option<Duration>,
+    clock: Box<dyn Clock>,
+    // (expires_at, node index), earliest first. Entries go stale when a slot is
+    // reused or a TTL is refreshed, so every pop is re-checked against the node.
+    expiry: BinaryHeap<Reverse<(Instant, usize)>>,
+    // Weighted mode: evict until total_weight <= max_weight.
+    // Unweighted caches keep max_weight = usize::MAX and every node weighs 1.
+    max_weight: usize,
+    total_weight: usize,
+    weigher: Option<Weigher<K, V>>,
+    listener: Option<EvictionListener<K, V>>,
+    // Aging: every `aging_period` accesses all frequencies are halved, so a key
+    // that was hot once cannot sit above the eviction line forever. 0 = off.
+    aging_period: usize,
+    accesses_since_aging: usize,
+    // TinyLFU: when full, a new key is only admitted if the sketch says it is
+    // requested more often than the entry it would evict
+    admission: Option<FrequencySketch>,
+    // Entries set by pin(); eviction only looks at the rest
+    pinned_count: usize,
+    pinned_weight: usize,
+    // Only the counters are kept up to date; stats() fills in the rest
+    stats: CacheStats,
 }
 
-impl LFUCache {
+impl<K: Hash + Eq + Clone, V> LFUCache<K, V> {
     pub fn new(capacity: usize) -> Self {
         LFUCache {
             capacity,
-            min_freq: 0,
             key_map: HashMap::new(),
-            freq_heads: HashMap::new(),
-            freq_tails: HashMap::new(),
-            nodes: Vec::with_capacity(capacity),
+            slab: LinkedSlab::with_capacity(0, capacity),
+            buckets: Vec::new(),
+            first_bucket: None,
+            default_ttl: None,
+            clock: Box::new(SystemClock),
+            expiry: BinaryHeap::new(),
+            max_weight: usize::MAX,
+            total_weight: 0,
+            weigher: None,
+            listener: None,
+            aging_period: 0,
+            accesses_since_aging: 0,
+            admission: None,
+            pinned_count: 0,
+            pinned_weight: 0,
+            stats: CacheStats::default(),
         }
     }
 
-    // Helper: Remove node from its frequency list
-    fn remove_from_freq_list(&mut self, node_idx: usize) {
+    /// Cache bounded by total weight (e.g. bytes) instead of entry count.
+    /// Weights come from put_weighted() or from a weigher set with with_weigher().
+    pub fn weighted(max_weight: usize) -> Self {
+        LFUCache {
+            capacity: usize::MAX,
+            max_weight,
+            ..Self::new(0)
+        }
+    }
+
+    /// Compute each entry's weight on put() instead of counting it as 1.
+    pub fn with_weigher<F: Fn(&K, &V) -> usize + Send + 'static>(mut self, weigher: F) -> Self {
+        self.weigher = Some(Box::new(weigher));
+        self
+    }
+
+    /// Call `listener` for every entry that leaves the cache, with the reason.
+    /// Useful for writing dirty entries back to storage or counting evictions.
+    pub fn with_eviction_listener<F>(mut self, listener: F) -> Self
+    where
+        F: FnMut(&K, &V, EvictionCause) + Send + 'static,
+    {
+        self.listener = Some(Box::new(listener));
+        self
+    }
+
+    /// Halve every entry's frequency (rounding down, never below 1) once per
+    /// `period` accesses (get hits and puts). A period of at least the capacity keeps
+    /// the O(n) rebuild amortized O(1) per access.
+    pub fn with_aging(mut self, period: usize) -> Self {
+        self.aging_period = period;
+        self
+    }
+
+    /// Put a TinyLFU admission filter in front of eviction. On a full cache a new key
+    /// only replaces the LFU/LRU victim if it has been requested (get or put, hit or miss)
+    /// more often recently; otherwise put() hands it back. Keeps one-hit scans out.
+    /// The sketch is sized from the capacity (256 to 65536 counters per row).
+    pub fn with_admission_filter(mut self) -> Self {
+        self.admission = Some(FrequencySketch::new(self.capacity));
+        self
+    }
+
+    pub fn total_weight(&self) -> usize {
+        self.total_weight
+    }
+
+    /// Every put() without an explicit TTL expires `ttl` after it was written.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.default_ttl = Some(ttl);
+        self
+    }
 
-        let freq = self.nodes[node_idx].freq;
-        let prev_opt = self.nodes[node_idx].prev;
-        let next_opt = self.nodes[node_idx].next;
+    /// Replace the wall clock (tests pass a ManualClock).
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    fn node(&self, node_idx: usize) -> &Node<K, V> {
+        self.slab.get(node_idx)
+    }
 
-        if let Some(prev_idx) = prev_opt {
-            self.nodes[prev_idx].next = next_opt;
+    fn node_mut(&mut self, node_idx: usize) -> &mut Node<K, V> {
+        self.slab.get_mut(node_idx)
+    }
+
+    // Helper: Remove node from its frequency list, freeing the bucket if it empties.
+    // Returns a bucket to pass as add_to_freq_list's hint when the node is re-added
+    // at a higher freq: its old bucket, or the one before it if that was freed.
+    fn remove_from_freq_list(&mut self, node_idx: usize) -> Option<usize> {
+        let bucket_idx = self.slab.unlink(node_idx);
+        self.release_bucket_if_empty(bucket_idx)
+    }
+
+    // Helper: Free a bucket whose last node just left. Returns the hint described
+    // on remove_from_freq_list: the bucket itself if it stays, else the one before it.
+    fn release_bucket_if_empty(&mut self, bucket_idx: usize) -> Option<usize> {
+        if self.slab.list_len(bucket_idx) > 0 {
+            return Some(bucket_idx);
         }
-        else
-        { // I am in head
-            if let Some(next_idx) = next_opt {
-                self.freq_heads.insert(freq, next_idx);
+        let before = self.buckets[bucket_idx].prev;
+        let after = self.buckets[bucket_idx].next;
+        match before {
+            Some(before_idx) => self.buckets[before_idx].next = after,
+            None => self.first_bucket = after,
+        }
+        if let Some(after_idx) = after {
+            self.buckets[after_idx].prev = before;
+        }
+        self.slab.free_list(bucket_idx);
+        before
+    }
+
+    // Helper: Add node to the head of the bucket for its freq (MRU position for that freq).
+    fn add_to_freq_list(&mut self, node_idx: usize, hint: Option<usize>) {
+        let bucket_idx = self.bucket_for(self.node(node_idx).freq, hint);
+        self.slab.link_front(bucket_idx, node_idx);
+    }
+
+    // Helper: The bucket for `freq`, opened if there is none yet. The search starts
+    // at `hint`, a bucket whose freq is at most `freq` (None = the first bucket):
+    // O(1) for a bump or a Freq 1 insert.
+    fn bucket_for(&mut self, freq: usize, hint: Option<usize>) -> usize {
+        let (mut before, mut cursor) = match hint {
+            Some(bucket_idx) => (self.buckets[bucket_idx].prev, Some(bucket_idx)),
+            None => (None, self.first_bucket),
+        };
+        while let Some(bucket_idx) = cursor {
+            let bucket = &self.buckets[bucket_idx];
+            if bucket.freq == freq {
+                return bucket_idx;
             }
-            else
-            { // head with no item next alone head -
-                self.freq_heads.remove(&freq);
+            if bucket.freq > freq {
+                break;
             }
+            before = cursor;
+            cursor = bucket.next;
+        }
+
+        // No bucket for this freq yet: open one between `before` and `cursor`
+        let bucket = Bucket {
+            freq,
+            prev: before,
+            next: cursor,
+        };
+        let bucket_idx = self.slab.add_list();
+        if bucket_idx == self.buckets.len() {
+            self.buckets.push(bucket);
+        } else {
+            self.buckets[bucket_idx] = bucket;
+        }
+        match before {
+            Some(before_idx) => self.buckets[before_idx].next = Some(bucket_idx),
+            None => self.first_bucket = Some(bucket_idx),
+        }
+        if let Some(after_idx) = cursor {
+            self.buckets[after_idx].prev = Some(bucket_idx);
+        }
+        bucket_idx
+    }
+
+    // Helper: Lowest freq in the cache, 0 when empty
+    fn min_freq(&self) -> usize {
+        self.first_bucket.map_or(0, |bucket_idx| self.buckets[bucket_idx].freq)
+    }
 
+    // Helper: has this node's TTL run out?
+    // Only nodes with a TTL read the clock, so TTL-free caches never pay for it.
+    fn is_expired(&self, node_idx: usize) -> bool {
+        let node = self.node(node_idx);
+        match node.expires_at {
+            Some(deadline) => !node.pinned && deadline <= self.clock.now(),
+            None => false,
         }
-        if let Some(next_idx) = next_opt {
-            self.nodes[next_idx].prev = prev_opt;
+    }
+
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if let Some(sketch) = self.admission.as_mut() {
+            sketch.increment(key);
+        }
+        let key_query_result = self.key_map.get(key);
+
+        if let Some(&node_idx) = key_query_result {
+            if self.is_expired(node_idx) {
+                self.discard_node(node_idx, EvictionCause::Expired);
+                self.stats.misses += 1;
+                return None;
+            }
+            let hint = self.remove_from_freq_list(node_idx);
+            self.node_mut(node_idx).freq += 1;
+            self.add_to_freq_list(node_idx, hint);
+            self.record_access();
+            self.stats.hits += 1;
+            Some(&self.node(node_idx).val)
         }
         else
         {
-            // tail because there is no next
-            if let Some(prev_idx) = prev_opt{
-                // no next and some prev
-                self.freq_tails.insert(freq, prev_idx);
+            self.stats.misses += 1;
+            None
+        }
+    }
+
+    /// Look at a value without counting it as an access: freq and recency stay put.
+    /// An entry whose TTL ran out reads as absent (it is reclaimed later).
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node_idx = *self.key_map.get(key)?;
+        if self.is_expired(node_idx) {
+            return None;
+        }
+        Some(&self.node(node_idx).val)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.peek(key).is_some()
+    }
+
+    /// Entries held, counting expired ones that have not been reclaimed yet.
+    pub fn len(&self) -> usize {
+        self.key_map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.key_map.is_empty()
+    }
+
+    /// Entry limit from new(); usize::MAX for a weighted() cache.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Live entries in eviction order: lowest freq first, and within one
+    /// frequency list least recently used (tail) first. Expired entries are skipped.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            cache: self,
+            bucket: self.first_bucket,
+            cursor: None,
+        }
+    }
+
+    /// Return the cached value for `key`, or call `loader`, cache its result and
+    /// return that. Counts as one access either way: one frequency bump on a hit,
+    /// a fresh Freq 1 entry on a miss - unlike get() followed by put().
+    /// None only if the loaded value could not be kept (zero capacity, over the
+    /// weight budget, refused by the admission filter). Entries evicted to make
+    /// room go to the eviction listener.
+    pub fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, loader: F) -> Option<&V> {
+        match self.try_get_or_insert_with(key, || Ok::<V, std::convert::Infallible>(loader())) {
+            Ok(cached) => cached,
+            Err(never) => match never {},
+        }
+    }
+
+    /// get_or_insert_with() for a loader that can fail. On Err nothing is cached.
+    pub fn try_get_or_insert_with<F, E>(&mut self, key: K, loader: F) -> Result<Option<&V>, E>
+    where
+        F: FnOnce() -> Result<V, E>,
+    {
+        match self.entry(key) {
+            Entry::Occupied(entry) => Ok(Some(entry.into_mut())),
+            Entry::Vacant(entry) => {
+                let value = loader()?;
+                Ok(entry.insert(value).map(|value| &*value))
+            }
+        }
+    }
+
+    /// Look `key` up once for in-place inspection or insertion, as with HashMap::entry.
+    /// The lookup counts as one access: a live entry gets its frequency bump here and
+    /// a hit is counted; otherwise a miss is counted (an expired entry is dropped first).
+    /// Like get(), only a hit advances the aging clock; VacantEntry::insert counts as a put.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+        if let Some(sketch) = self.admission.as_mut() {
+            sketch.increment(&key);
+        }
+        if let Some(&node_idx) = self.key_map.get(&key) {
+            if !self.is_expired(node_idx) {
+                self.record_access();
+                let hint = self.remove_from_freq_list(node_idx);
+                self.node_mut(node_idx).freq += 1;
+                self.add_to_freq_list(node_idx, hint);
+                self.stats.hits += 1;
+                return Entry::Occupied(OccupiedEntry { cache: self, node_idx });
+            }
+            self.discard_node(node_idx, EvictionCause::Expired);
+        }
+        self.stats.misses += 1;
+        Entry::Vacant(VacantEntry { cache: self, key })
+    }
+
+    /// Insert or update `key`. Returns the entries evicted to make room, oldest victim first:
+    /// at most one for a count-bounded cache, possibly several for a weighted one.
+    /// An entry that is not kept (capacity 0, weight over budget, refused by the
+    /// admission filter, no unpinned entry to evict) is handed straight back.
+    /// A value replaced under the same key is not returned; the listener sees it as Replaced.
+    pub fn put(&mut self, key: K, value: V) -> Vec<(K, V)> {
+        let ttl = self.default_ttl;
+        let weight = self.weigh(&key, &value);
+        self.put_inner(key, value, ttl, weight)
+    }
+
+    /// put(), but a cache too full of pinned entries to make room is an error
+    /// rather than one more handed-back pair.
+    pub fn try_put(&mut self, key: K, value: V) -> Result<Vec<(K, V)>, AllPinned<K, V>> {
+        let ttl = self.default_ttl;
+        let weight = self.weigh(&key, &value);
+        self.try_put_inner(key, value, ttl, weight)
+    }
+
+    /// put() with a TTL for this entry only, overriding with_ttl().
+    pub fn put_with_ttl(&mut self, key: K, value: V, ttl: Duration) -> Vec<(K, V)> {
+        let weight = self.weigh(&key, &value);
+        self.put_inner(key, value, Some(ttl), weight)
+    }
+
+    /// put() with an explicit weight, overriding the weigher.
+    /// If `weight` alone is over the budget the pair is handed back and any old value for `key` is dropped.
+    pub fn put_weighted(&mut self, key: K, value: V, weight: usize) -> Vec<(K, V)> {
+        let ttl = self.default_ttl;
+        self.put_inner(key, value, ttl, weight)
+    }
+
+    fn weigh(&self, key: &K, value: &V) -> usize {
+        match &self.weigher {
+            Some(weigher) => weigher(key, value),
+            None => 1,
+        }
+    }
+
+    fn put_inner(&mut self, key: K, value: V, ttl: Option<Duration>, weight: usize) -> Vec<(K, V)> {
+        self.try_put_inner(key, value, ttl, weight)
+            .unwrap_or_else(|AllPinned { key, value }| vec![(key, value)])
+    }
+
+    fn try_put_inner(&mut self, key: K, value: V, ttl: Option<Duration>, weight: usize) -> Result<Vec<(K, V)>, AllPinned<K, V>> {
+        self.record_access();
+        if let Some(sketch) = self.admission.as_mut() {
+            sketch.increment(&key);
+        }
+        self.store(key, value, ttl, weight).map(|(evicted, _)| evicted)
+    }
+
+    // Helper: put() minus the access accounting (aging tick, admission sketch),
+    // so callers that already counted this access do not count it twice.
+    // Also returns the stored node's index, for callers that hand out a reference.
+    fn store(&mut self, key: K, value: V, ttl: Option<Duration>, weight: usize) -> Result<Inserted<K, V>, AllPinned<K, V>> {
+        if self.capacity == 0 || weight > self.max_weight
+        {
+            // Could never fit. Do not leave an older value behind under this key.
+            if let Some(&node_idx) = self.key_map.get(&key) {
+                self.discard_node(node_idx, EvictionCause::Replaced);
+            }
+            self.stats.rejections += 1;
+            return Ok((vec![(key, value)], None));
+        }
+        let expires_at = ttl.map(|ttl| self.clock.now() + ttl);
+        let mut freq = 1;
+        let mut pinned = false;
+        if let Some(&node_idx) = self.key_map.get(&key)
+        {
+            let old_weight = self.node(node_idx).weight;
+            if self.is_expired(node_idx) {
+                // A dead entry must not hand its frequency to the new value
+                self.discard_node(node_idx, EvictionCause::Expired);
+            }
+            else if self.total_weight - old_weight + weight <= self.max_weight
+            {
+                let hint = self.remove_from_freq_list(node_idx);
+                let old_value = std::mem::replace(&mut self.node_mut(node_idx).val, value);
+                self.node_mut(node_idx).freq +=1;
+                self.node_mut(node_idx).expires_at = expires_at;
+                self.node_mut(node_idx).weight = weight;
+                self.total_weight = self.total_weight - old_weight + weight;
+                if self.node(node_idx).pinned {
+                    self.pinned_weight = self.pinned_weight - old_weight + weight;
+                }
+                self.add_to_freq_list(node_idx, hint);
+                self.schedule_expiry(node_idx);
+                self.record_removal(&key, &old_value, EvictionCause::Replaced);
+                self.stats.updates += 1;
+
+                return Ok((Vec::new(), Some(node_idx)));
+            }
+            else if self.blocked_by_pins(weight, Some(node_idx))
+            {
+                // Checked before the old entry is taken out, so a refusal leaves it as it was
+                self.stats.rejections += 1;
+                return Err(AllPinned { key, value });
             }
             else
             {
-                self.freq_tails.remove(&freq);
+                // Heavier value no longer fits. Take the entry out so eviction below
+                // cannot pick it, then re-insert it one frequency higher.
+                let old = self.remove_node(node_idx);
+                freq = old.freq + 1;
+                pinned = old.pinned;
+                self.record_removal(&old.key, &old.val, EvictionCause::Replaced);
+            }
+        }
+        let (evicted, new_idx) = self.insert_new(key, value, expires_at, weight, freq)?;
+        if let Some(new_idx) = new_idx.filter(|_| pinned) {
+            self.set_pinned(new_idx, true);
+        }
+        Ok((evicted, new_idx))
+    }
+
+    // Helper: The insert half of store(), for a key known to be absent.
+    // Also returns the new node's index, or None if the entry was not admitted.
+    fn insert_new(&mut self, key: K, value: V, expires_at: Option<Instant>, weight: usize, freq: usize) -> Result<Inserted<K, V>, AllPinned<K, V>> {
+        // INSERT case - cache has space
+        if self.key_map.len() < self.capacity && self.total_weight + weight <= self.max_weight {
+            let new_idx = self.insert_new_node(key, value, expires_at, weight, freq);
+
+            return Ok((Vec::new(), Some(new_idx)));
+        }
+
+        // Pinned entries cannot be evicted; check the rest can make room before touching anything
+        if self.blocked_by_pins(weight, None) {
+            self.stats.rejections += 1;
+            return Err(AllPinned { key, value });
+        }
+
+        // ADMISSION check - a brand new key must beat the victim it would replace.
+        // Expired entries are free to reclaim, so they never block admission.
+        // (Weighted caches compare against the first victim only.)
+        if freq == 1 && self.admission.is_some() && self.peek_expired().is_none() {
+            let victim_idx = self.lfu_victim().expect("room was checked above");
+            let sketch = self.admission.as_ref().expect("checked above");
+            if sketch.estimate(&key) <= sketch.estimate(&self.node(victim_idx).key) {
+                self.stats.rejections += 1;
+                return Ok((vec![(key, value)], None));
+            }
+        }
+
+        // EVICTION case - cache is full (by count or by weight)
+        let mut evicted = Vec::new();
+        loop {
+            let (victim, cause) = self.evict_lfu_node().expect("room was checked above");
+            self.record_removal(&victim.key, &victim.val, cause);
+            evicted.push((victim.key, victim.val));
+
+            // One victim may not be enough for a heavy entry; keep going until it fits
+            if self.total_weight + weight <= self.max_weight {
+                let new_idx = self.insert_new_node(key, value, expires_at, weight, freq);
+                return Ok((evicted, Some(new_idx)));
+            }
+        }
+    }
+
+    /// Delete `key` and hand back its value. The slot goes on the free list for the
+    /// next put(), and min_freq moves up if this was the last entry at that frequency.
+    /// An expired entry is dropped too, but reads as absent.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node_idx = *self.key_map.get(key)?;
+        if self.is_expired(node_idx) {
+            self.discard_node(node_idx, EvictionCause::Expired);
+            return None;
+        }
+        let node = self.remove_node(node_idx);
+        self.record_removal(&node.key, &node.val, EvictionCause::Explicit);
+        Some(node.val)
+    }
+
+    /// Change the entry limit at runtime. Growing takes effect at once; shrinking
+    /// evicts the surplus in eviction order (expired first, then LFU/LRU) and returns it.
+    pub fn set_capacity(&mut self, capacity: usize) -> Vec<(K, V)> {
+        self.capacity = capacity;
+        self.evict_to_limits()
+    }
+
+    /// set_capacity() for the weight budget of a weighted() cache.
+    pub fn set_max_weight(&mut self, max_weight: usize) -> Vec<(K, V)> {
+        self.max_weight = max_weight;
+        self.evict_to_limits()
+    }
+
+    // Helper: Evict until both the entry count and the total weight are within limits
+    fn evict_to_limits(&mut self) -> Vec<(K, V)> {
+        let mut evicted = Vec::new();
+        while self.key_map.len() > self.capacity || self.total_weight > self.max_weight {
+            // Only pinned entries left: stay over the limit until some are unpinned
+            let Some((victim, cause)) = self.evict_lfu_node() else { break };
+            self.record_removal(&victim.key, &victim.val, cause);
+            evicted.push((victim.key, victim.val));
+        }
+        evicted
+    }
+
+    /// Keep `key` resident until unpin() or remove(): eviction skips it and its TTL
+    /// is suspended. Returns false if there is no live entry for `key`.
+    /// Pins are not saved in snapshots.
+    pub fn pin<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match self.key_map.get(key) {
+            Some(&node_idx) if !self.is_expired(node_idx) => {
+                self.set_pinned(node_idx, true);
+                true
             }
+            _ => false,
         }
-        if freq == self.min_freq && prev_opt.is_none() && next_opt.is_none() {
-            self.min_freq += 1;
     }
 
+    /// Make `key` evictable again; a TTL that ran out while pinned takes effect now.
+    /// If pins had held the cache over its limits, it is trimmed back here (the
+    /// entries evicted go to the eviction listener). Returns false if `key` is not cached.
+    pub fn unpin<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let Some(&node_idx) = self.key_map.get(key) else {
+            return false;
+        };
+        self.set_pinned(node_idx, false);
+        self.schedule_expiry(node_idx);
+        self.evict_to_limits();
+        true
     }
 
-    // Helper: Add node to the head of the frequency list (MRU position for that freq)
-    fn add_to_freq_list(&mut self, node_idx: usize) {
-        let freq = self.nodes[node_idx].freq;
+    /// Drop every entry whose TTL has run out. Returns how many were dropped.
+    pub fn purge_expired(&mut self) -> usize {
+        let mut purged = 0;
+        while let Some(node_idx) = self.pop_expired() {
+            self.discard_node(node_idx, EvictionCause::Expired);
+            purged += 1;
+        }
+        purged
+    }
 
-        if let Some(&old_head_idx) = self.freq_heads.get(&freq) {
-            self.nodes[node_idx].prev = None;
-            self.nodes[node_idx].next = Some(old_head_idx);
-            self.nodes[old_head_idx].prev = Some(node_idx);
-            self.freq_heads.insert(freq, node_idx);
+    /// Counters plus a per-frequency entry count walked from the frequency buckets.
+    /// O(n) in the number of entries; meant for periodic logging, not the hot path.
+    pub fn stats(&self) -> CacheStats {
+        let mut freq_histogram = Vec::new();
+        let mut bucket_cursor = self.first_bucket;
+        while let Some(bucket_idx) = bucket_cursor {
+            let bucket = &self.buckets[bucket_idx];
+            freq_histogram.push((bucket.freq, self.slab.list_len(bucket_idx)));
+            bucket_cursor = bucket.next;
         }
-        else
-        {
-            self.nodes[node_idx].prev = None;
-            self.nodes[node_idx].next = None;
-            self.freq_heads.insert(freq, node_idx);
-            self.freq_tails.insert(freq, node_idx);
+        CacheStats {
+            len: self.key_map.len(),
+            total_weight: self.total_weight,
+            min_freq: self.min_freq(),
+            freq_histogram,
+            ..self.stats.clone()
+        }
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = CacheStats::default();
+    }
+
+    pub fn clear(&mut self) {
+        self.key_map.clear();
+        self.slab.clear();
+        self.clear_buckets();
+        self.expiry.clear();
+        self.total_weight = 0;
+        self.pinned_count = 0;
+        self.pinned_weight = 0;
+    }
+    // Helper: Evict the LFU/LRU node and hand it back; its slot is the next one reused.
+    // None if the cache is empty or every entry is pinned.
+fn evict_lfu_node(&mut self) -> Option<(Node<K, V>, EvictionCause)> {
+    // Step 0: An expired entry is always the cheapest victim - take it before any live one
+    if let Some(expired_idx) = self.pop_expired() {
+        return Some((self.remove_node(expired_idx), EvictionCause::Expired));
+    }
+
+    // Step 1: Find the LRU unpinned node at the lowest frequency
+    let evict_idx = self.lfu_victim()?;
+
+    // Step 2-4: Remove from key_map and frequency list, free the slot
+    Some((self.remove_node(evict_idx), EvictionCause::Capacity))
+}
+    // Helper: The entry evict_lfu_node picks when nothing expired: the tail of the
+    // min_freq list, or if that is pinned the next unpinned one walking up.
+    fn lfu_victim(&self) -> Option<usize> {
+        if self.key_map.len() == self.pinned_count {
+            return None;
+        }
+        let mut bucket_cursor = self.first_bucket;
+        while let Some(bucket_idx) = bucket_cursor {
+            let mut cursor = self.slab.back(bucket_idx);
+            while let Some(node_idx) = cursor {
+                if !self.node(node_idx).pinned {
+                    return Some(node_idx);
+                }
+                cursor = self.slab.prev(node_idx);
+            }
+            bucket_cursor = self.buckets[bucket_idx].next;
+        }
+        None
+    }
+
+    // Helper: Flip a node's pin and keep the pinned totals in step
+    fn set_pinned(&mut self, node_idx: usize, pinned: bool) {
+        let node = self.node_mut(node_idx);
+        if node.pinned == pinned {
+            return;
+        }
+        node.pinned = pinned;
+        let weight = node.weight;
+        if pinned {
+            self.pinned_count += 1;
+            self.pinned_weight += weight;
+        } else {
+            self.pinned_count -= 1;
+            self.pinned_weight -= weight;
+        }
+    }
+    // Helper: Forget every bucket (the nodes are being dropped or relinked)
+    fn clear_buckets(&mut self) {
+        self.slab.clear_lists();
+        self.buckets.clear();
+        self.first_bucket = None;
+    }
+
+    // Helper: True if, leaving out `except`, every entry is pinned or the pinned
+    // weight alone leaves no room for `weight` - eviction could never make space.
+    fn blocked_by_pins(&self, weight: usize, except: Option<usize>) -> bool {
+        let (mut entries, mut pinned_count, mut pinned_weight) = (self.key_map.len(), self.pinned_count, self.pinned_weight);
+        if let Some(node_idx) = except {
+            entries -= 1;
+            if self.node(node_idx).pinned {
+                pinned_count -= 1;
+                pinned_weight -= self.node(node_idx).weight;
+            }
+        }
+        entries == pinned_count || pinned_weight + weight > self.max_weight
+    }
+
+    // Helper: Take a node out of key_map and its frequency list for good and free its slot
+    fn remove_node(&mut self, node_idx: usize) -> Node<K, V> {
+        let bucket_idx = self.slab.list_of(node_idx);
+        let node = self.slab.remove(node_idx);
+        self.release_bucket_if_empty(bucket_idx);
+        self.key_map.remove(&node.key);
+        self.total_weight -= node.weight;
+        if node.pinned {
+            self.pinned_count -= 1;
+            self.pinned_weight -= node.weight;
+        }
+        node
+    }
+
+    // Helper: Count one access and halve all frequencies when the aging period is up
+    fn record_access(&mut self) {
+        if self.aging_period == 0 {
+            return;
+        }
+        self.accesses_since_aging += 1;
+        if self.accesses_since_aging >= self.aging_period {
+            self.accesses_since_aging = 0;
+            self.halve_frequencies();
+        }
+    }
+
+    // Helper: Rebuild every frequency list with freq / 2 (at least 1).
+    // Lists f = 2k and 2k + 1 merge into list k. Walking lowest freq first, LRU to MRU,
+    // and pushing each node at the head keeps recency order inside a list, and puts
+    // the formerly higher-freq nodes on the MRU side of the merged list.
+    fn halve_frequencies(&mut self) {
+        let mut order = Vec::with_capacity(self.key_map.len());
+        let mut bucket_cursor = self.first_bucket;
+        while let Some(bucket_idx) = bucket_cursor {
+            let mut cursor = self.slab.back(bucket_idx);
+            while let Some(node_idx) = cursor {
+                order.push(node_idx);
+                cursor = self.slab.prev(node_idx);
+            }
+            bucket_cursor = self.buckets[bucket_idx].next;
+        }
+
+        // Halved freqs never decrease along `order`, so each node's bucket is at
+        // or after the previous node's
+        self.clear_buckets();
+        let mut hint = None;
+        for node_idx in order {
+            let node = self.node_mut(node_idx);
+            node.freq = (node.freq / 2).max(1);
+            self.add_to_freq_list(node_idx, hint);
+            hint = Some(self.slab.list_of(node_idx));
+        }
+    }
+
+    // Helper: remove_node() plus the listener call
+    fn discard_node(&mut self, node_idx: usize, cause: EvictionCause) {
+        let node = self.remove_node(node_idx);
+        self.record_removal(&node.key, &node.val, cause);
+    }
+
+    // Helper: Count an entry leaving the cache and tell the listener
+    fn record_removal(&mut self, key: &K, value: &V, cause: EvictionCause) {
+        match cause {
+            EvictionCause::Capacity => self.stats.evictions += 1,
+            EvictionCause::Expired => self.stats.expirations += 1,
+            EvictionCause::Explicit => self.stats.removals += 1,
+            EvictionCause::Replaced => {}
+        }
+        if let Some(listener) = self.listener.as_mut() {
+            listener(key, value, cause);
+        }
+    }
+
+    // Helper: Pop the expiry heap until it yields a live node whose TTL is over.
+    // Stale heap entries (refreshed TTL, reused slot) fail the re-check and are dropped.
+    fn pop_expired(&mut self) -> Option<usize> {
+        let node_idx = self.peek_expired()?;
+        self.expiry.pop();
+        Some(node_idx)
+    }
+
+    // Helper: Like pop_expired, but leaves the expired node's entry on top of the heap
+    fn peek_expired(&mut self) -> Option<usize> {
+        // Nothing scheduled: caches without TTLs never read the clock on eviction
+        self.expiry.peek()?;
+        let now = self.clock.now();
+        while let Some(&Reverse((deadline, node_idx))) = self.expiry.peek() {
+            if deadline > now {
+                return None;
+            }
+            let still_expired = match self.slab.try_get(node_idx) {
+                Some(node) => !node.pinned && node.expires_at.is_some_and(|at| at <= now),
+                None => false,
+            };
+            if still_expired {
+                return Some(node_idx);
+            }
+            self.expiry.pop();
+        }
+        None
+    }
+
+    // Helper: Record a node's deadline in the expiry heap
+    fn schedule_expiry(&mut self, node_idx: usize) {
+        if let Some(deadline) = self.node(node_idx).expires_at {
+            // Refreshed TTLs leave old entries behind; rebuild before they pile up
+            if self.expiry.len() > 2 * self.key_map.len() + 16 {
+                let live: Vec<_> = self.slab.iter()
+                    .filter_map(|(idx, node)| Some(Reverse((node.expires_at?, idx))))
+                    .collect();
+                self.expiry = live.into_iter().collect();
+            }
+            self.expiry.push(Reverse((deadline, node_idx)));
+        }
+    }
+
+    // Helper: Insert a new node and return its index; the slab reuses a freed slot if it has one.
+    // freq is 1 for a brand new key; a re-inserted key keeps its count.
+    fn insert_new_node(&mut self, key: K, value: V, expires_at: Option<Instant>, weight: usize, freq: usize) -> usize {
+    let node = Node {
+        key: key.clone(),
+        val: value,
+        freq,
+        expires_at,
+        weight,
+        pinned: false,
+    };
+    let bucket_idx = self.bucket_for(freq, None);
+    let node_idx = self.slab.push_front(bucket_idx, node);
+
+    self.key_map.insert(key, node_idx);
+    self.total_weight += weight;
+    // freq > 1 only when an update had to move out and back in
+    if freq == 1 {
+        self.stats.inserts += 1;
+    } else {
+        self.stats.updates += 1;
+    }
+    self.schedule_expiry(node_idx);
+    node_idx
+}
+
+}
+
+impl<K: Hash + Eq + Clone + Debug, V: Debug> LFUCache<K, V> {
+    pub fn display(&self) {
+        println!("Cache contents:");
+        for (_, node) in self.slab.iter() {
+            println!("  Key: {:?}, Val: {:?}, Freq: {}", node.key, node.val, node.freq);
+        }
+    }
+}
+
+/// Memoizes an expensive pure function in an LFUCache: call() returns the cached
+/// result for an argument seen before, and otherwise runs the function once and caches it.
+pub struct Memoized<A, R, F> {
+    cache: LFUCache<A, R>,
+    func: F,
+}
+
+impl<A: Hash + Eq + Clone, R: Clone, F: FnMut(&A) -> R> Memoized<A, R, F> {
+    pub fn new(capacity: usize, func: F) -> Self {
+        Memoized {
+            cache: LFUCache::new(capacity),
+            func,
+        }
+    }
+
+    /// Memoize into a cache configured by the caller (TTL, weigher, admission filter...).
+    pub fn with_cache(cache: LFUCache<A, R>, func: F) -> Self {
+        Memoized { cache, func }
+    }
 
+    pub fn call(&mut self, arg: A) -> R {
+        match self.cache.entry(arg) {
+            Entry::Occupied(entry) => entry.get().clone(),
+            Entry::Vacant(entry) => {
+                let result = (self.func)(entry.key());
+                // Only a kept result is cloned; a refused one comes back whole
+                match entry.try_insert(result) {
+                    Ok(cached) => cached.clone(),
+                    Err(result) => result,
+                }
+            }
+        }
+    }
+
+    /// The underlying cache, e.g. for stats().
+    pub fn cache(&self) -> &LFUCache<A, R> {
+        &self.cache
+    }
+}
+
+/// A view into one key of an LFUCache, from LFUCache::entry().
+pub enum Entry<'a, K, V> {
+    Occupied(OccupiedEntry<'a, K, V>),
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+/// A live entry. Its frequency was already bumped by entry().
+pub struct OccupiedEntry<'a, K, V> {
+    cache: &'a mut LFUCache<K, V>,
+    node_idx: usize,
+}
+
+/// A key with no live entry.
+pub struct VacantEntry<'a, K, V> {
+    cache: &'a mut LFUCache<K, V>,
+    key: K,
+}
+
+impl<'a, K: Hash + Eq + Clone, V> Entry<'a, K, V> {
+    /// The value, inserting `default` if there is none. None only if the cache
+    /// refused the new value (zero capacity, over the weight budget, admission filter).
+    pub fn or_insert(self, default: V) -> Option<&'a mut V> {
+        match self {
+            Entry::Occupied(entry) => Some(entry.into_mut()),
+            Entry::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    /// or_insert() with a value built only when the key is vacant.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> Option<&'a mut V> {
+        match self {
+            Entry::Occupied(entry) => Some(entry.into_mut()),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Update a live value in place before any or_insert* call. Does not count as
+    /// another access.
+    pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+        if let Entry::Occupied(entry) = &mut self {
+            f(entry.get_mut());
+        }
+        self
+    }
+
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+}
+
+impl<'a, K: Hash + Eq + Clone, V: Default> Entry<'a, K, V> {
+    pub fn or_default(self) -> Option<&'a mut V> {
+        self.or_insert_with(V::default)
+    }
+}
+
+// The entry keeps the weight and TTL it was stored with; put() re-weighs and refreshes them
+impl<'a, K: Hash + Eq + Clone, V> OccupiedEntry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        &self.cache.node(self.node_idx).key
+    }
+
+    pub fn get(&self) -> &V {
+        &self.cache.node(self.node_idx).val
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.cache.node_mut(self.node_idx).val
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.cache.node_mut(self.node_idx).val
+    }
+
+    /// Swap in a new value and return the old one (the listener sees it as Replaced).
+    pub fn insert(&mut self, value: V) -> V {
+        let old_value = std::mem::replace(self.get_mut(), value);
+        let key = self.key().clone();
+        self.cache.record_removal(&key, &old_value, EvictionCause::Replaced);
+        self.cache.stats.updates += 1;
+        old_value
+    }
+
+    /// Take the entry out of the cache, as LFUCache::remove() does.
+    pub fn remove(self) -> V {
+        let node = self.cache.remove_node(self.node_idx);
+        self.cache.record_removal(&node.key, &node.val, EvictionCause::Explicit);
+        node.val
+    }
+}
+
+impl<'a, K: Hash + Eq + Clone, V> VacantEntry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Insert at Freq 1, evicting as put() would; evicted entries go to the
+    /// eviction listener. None if the cache refused the value.
+    pub fn insert(self, value: V) -> Option<&'a mut V> {
+        self.try_insert(value).ok()
+    }
+
+    // insert(), but a refused value comes back as Err instead of being dropped
+    fn try_insert(self, value: V) -> Result<&'a mut V, V> {
+        let cache = self.cache;
+        // entry() already fed the admission sketch; only the put's aging tick is left
+        cache.record_access();
+        let weight = cache.weigh(&self.key, &value);
+        let ttl = cache.default_ttl;
+        match cache.store(self.key, value, ttl, weight) {
+            Ok((_, Some(node_idx))) => Ok(&mut cache.node_mut(node_idx).val),
+            // A refused pair is the only one handed back
+            Ok((mut refused, None)) => Err(refused.pop().expect("refused pair is handed back").1),
+            Err(AllPinned { value, .. }) => Err(value),
+        }
+    }
+}
+
+/// Iterator from LFUCache::iter(): walks each frequency list tail -> head, lowest freq first.
+pub struct Iter<'a, K, V> {
+    cache: &'a LFUCache<K, V>,
+    // Next bucket to walk once `cursor` runs off the current one
+    bucket: Option<usize>,
+    cursor: Option<usize>,
+}
+
+impl<'a, K: Hash + Eq + Clone, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cache = self.cache;
+        loop {
+            match self.cursor {
+                Some(node_idx) => {
+                    let node = cache.node(node_idx);
+                    self.cursor = cache.slab.prev(node_idx);
+                    if !cache.is_expired(node_idx) {
+                        return Some((&node.key, &node.val));
+                    }
+                }
+                None => {
+                    let bucket_idx = self.bucket?;
+                    self.cursor = cache.slab.back(bucket_idx);
+                    self.bucket = cache.buckets[bucket_idx].next;
+                }
+            }
+        }
+    }
+}
+
+/// Byte encoding for keys and values written by LFUCache::save_snapshot().
+pub trait SnapshotCodec: Sized {
+    fn encode(&self, out: &mut Vec<u8>);
+    /// Reads one value from the front of `input` and advances it.
+    fn decode(input: &mut &[u8]) -> io::Result<Self>;
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+// Where to write a file before renaming it over `path`: the full file name
+// plus ".tmp", so cache.snap and cache.bak never share one and cache.tmp is not its own
+fn temp_path(path: &Path) -> io::Result<PathBuf> {
+    match path.file_name() {
+        Some(name) => {
+            let mut name = name.to_os_string();
+            name.push(".tmp");
+            Ok(path.with_file_name(name))
+        }
+        None => Err(io::Error::new(io::ErrorKind::InvalidInput, "path has no file name")),
+    }
+}
+
+// Split `n` bytes off the front of `input`, or fail on a truncated file
+fn take_bytes<'a>(input: &mut &'a [u8], n: usize) -> io::Result<&'a [u8]> {
+    if input.len() < n {
+        return Err(invalid_data("snapshot truncated"));
+    }
+    let (head, rest) = input.split_at(n);
+    *input = rest;
+    Ok(head)
+}
+
+macro_rules! int_snapshot_codec {
+    ($($t:ty),*) => {$(
+        impl SnapshotCodec for $t {
+            fn encode(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+            fn decode(input: &mut &[u8]) -> io::Result<Self> {
+                let bytes = take_bytes(input, std::mem::size_of::<$t>())?;
+                let array = std::convert::TryInto::try_into(bytes).expect("length checked by take_bytes");
+                Ok(<$t>::from_le_bytes(array))
+            }
+        }
+    )*};
+}
+
+int_snapshot_codec!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+// usize is always written as u64 so 32- and 64-bit builds read each other's files
+impl SnapshotCodec for usize {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u64).encode(out);
+    }
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        <usize as std::convert::TryFrom<u64>>::try_from(u64::decode(input)?).map_err(|_| invalid_data("usize out of range"))
+    }
+}
+
+impl SnapshotCodec for Vec<u8> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.len().encode(out);
+        out.extend_from_slice(self);
+    }
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        let len = usize::decode(input)?;
+        Ok(take_bytes(input, len)?.to_vec())
+    }
+}
+
+impl SnapshotCodec for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.len().encode(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        String::from_utf8(Vec::<u8>::decode(input)?).map_err(|_| invalid_data("string is not UTF-8"))
+    }
+}
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"LFUC";
+const SNAPSHOT_VERSION: u32 = 1;
+
+// Snapshot layout, all integers little-endian, usize as u64:
+//   magic "LFUC" | version u32 | capacity | max_weight | min_freq | list count
+//   per frequency list, lowest freq first:
+//     freq | entry count
+//     per entry, head (MRU) to tail (LRU):
+//       key | value | weight | has_ttl u8 | remaining TTL nanos u64 (only if has_ttl = 1)
+impl<K: Hash + Eq + Clone + SnapshotCodec, V: SnapshotCodec> LFUCache<K, V> {
+    /// Write every live entry with its freq and its position inside its frequency
+    /// list, so load_snapshot() reproduces the same eviction order.
+    /// Remaining TTLs are saved relative to now. Written to a temp file, then renamed.
+    pub fn save_snapshot<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let now = self.clock.now();
+        let mut buckets = Vec::new();
+        let mut bucket_cursor = self.first_bucket;
+        while let Some(bucket_idx) = bucket_cursor {
+            buckets.push(bucket_idx);
+            bucket_cursor = self.buckets[bucket_idx].next;
+        }
+
+        let mut out = Vec::new();
+        out.extend_from_slice(SNAPSHOT_MAGIC);
+        SNAPSHOT_VERSION.encode(&mut out);
+        self.capacity.encode(&mut out);
+        self.max_weight.encode(&mut out);
+        self.min_freq().encode(&mut out);
+        buckets.len().encode(&mut out);
+        for bucket_idx in buckets {
+            let freq = self.buckets[bucket_idx].freq;
+            // Expired entries are not worth restoring
+            let mut list = Vec::new();
+            let mut cursor = self.slab.front(bucket_idx);
+            while let Some(node_idx) = cursor {
+                if !self.is_expired(node_idx) {
+                    list.push(node_idx);
+                }
+                cursor = self.slab.next(node_idx);
+            }
+            freq.encode(&mut out);
+            list.len().encode(&mut out);
+            for node_idx in list {
+                let node = self.node(node_idx);
+                node.key.encode(&mut out);
+                node.val.encode(&mut out);
+                node.weight.encode(&mut out);
+                match node.expires_at {
+                    Some(deadline) => {
+                        1u8.encode(&mut out);
+                        (deadline.saturating_duration_since(now).as_nanos() as u64).encode(&mut out);
+                    }
+                    None => 0u8.encode(&mut out),
+                }
+            }
         }
-    }
 
-    pub fn get(&mut self, key: i32) -> Option<i32> {
-        let key_query_result = self.key_map.get(&key);
+        let path = path.as_ref();
+        let tmp = temp_path(path)?;
+        fs::write(&tmp, &out)?;
+        fs::rename(&tmp, path)
+    }
 
-        if let Some(&node_idx) = key_query_result {
-            let val_res = self.nodes[node_idx].val;
-            self.remove_from_freq_list(node_idx);
-            self.nodes[node_idx].freq += 1;
-            self.add_to_freq_list(node_idx);
-            return Some(val_res);
+    /// Replace this cache's contents (and its capacity / weight budget) with a
+    /// snapshot. Settings that are code - clock, listener, weigher, aging,
+    /// admission filter, default TTL - stay as configured on this cache.
+    pub fn load_snapshot<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let bytes = fs::read(path)?;
+        let mut input = &bytes[..];
+        if take_bytes(&mut input, 4)? != SNAPSHOT_MAGIC {
+            return Err(invalid_data("not an LFUCache snapshot"));
         }
-        else
-        {
-            return None;
+        let version = u32::decode(&mut input)?;
+        if version != SNAPSHOT_VERSION {
+            return Err(invalid_data(&format!("unsupported snapshot version {}", version)));
         }
-    }
+        let capacity = usize::decode(&mut input)?;
+        let max_weight = usize::decode(&mut input)?;
+        // min_freq is implied by the lists themselves
+        let _min_freq = usize::decode(&mut input)?;
+        let list_count = usize::decode(&mut input)?;
 
-    pub fn put(&mut self, key: i32, value: i32) -> bool {
-        if self.capacity == 0
-        {
-            return false;
+        // Decode everything before touching self, so a bad file leaves the cache as it was
+        let now = self.clock.now();
+        let mut lists = Vec::new();
+        let mut seen = std::collections::HashSet::new();
+        let mut total_weight = 0usize;
+        for _ in 0..list_count {
+            let freq = usize::decode(&mut input)?;
+            if freq == 0 {
+                return Err(invalid_data("frequency 0 in snapshot"));
+            }
+            let len = usize::decode(&mut input)?;
+            let mut list = Vec::new();
+            for _ in 0..len {
+                let key = K::decode(&mut input)?;
+                let val = V::decode(&mut input)?;
+                let weight = usize::decode(&mut input)?;
+                let expires_at = match u8::decode(&mut input)? {
+                    0 => None,
+                    1 => Some(now + Duration::from_nanos(u64::decode(&mut input)?)),
+                    _ => return Err(invalid_data("bad TTL flag")),
+                };
+                if !seen.insert(key.clone()) {
+                    return Err(invalid_data("duplicate key in snapshot"));
+                }
+                total_weight = total_weight.saturating_add(weight);
+                list.push((key, val, weight, expires_at));
+            }
+            lists.push((freq, list));
+        }
+        if !input.is_empty() {
+            return Err(invalid_data("trailing bytes after snapshot"));
+        }
+        if seen.len() > capacity || total_weight > max_weight {
+            return Err(invalid_data("snapshot holds more than its own capacity"));
         }
-        if let Some(&node_idx) = self.key_map.get(&key)
-        {
-            self.remove_from_freq_list(node_idx);
-            self.nodes[node_idx].val = value;
-            self.nodes[node_idx].freq +=1;
-            self.add_to_freq_list(node_idx);
 
-            return true;
+        self.key_map.clear();
+        self.slab.clear();
+        self.clear_buckets();
+        self.expiry.clear();
+        self.pinned_count = 0;
+        self.pinned_weight = 0;
+        self.capacity = capacity;
+        self.max_weight = max_weight;
+        self.total_weight = total_weight;
+        // Saved lowest freq first, so the last list's bucket is a valid search start
+        let mut hint = None;
+        for (freq, list) in lists {
+            hint = hint.filter(|&bucket_idx: &usize| self.buckets[bucket_idx].freq <= freq);
+            // push_front adds at the head, so feed each list tail first
+            for (key, val, weight, expires_at) in list.into_iter().rev() {
+                let bucket_idx = self.bucket_for(freq, hint);
+                let node_idx = self.slab.push_front(bucket_idx, Node {
+                    key: key.clone(),
+                    val,
+                    freq,
+                    expires_at,
+                    weight,
+                    pinned: false,
+                });
+                self.key_map.insert(key, node_idx);
+                hint = Some(bucket_idx);
+                self.schedule_expiry(node_idx);
+            }
         }
-        // INSERT case - cache has space
-        if self.nodes.len() < self.capacity {
-            let new_idx = self.nodes.len();
-
-            // Create and push placeholder node
-            self.nodes.push(Node {
-                key: 0,
-                val: 0,
-                freq: 0,
-                prev: None,
-                next: None,
-            });
+        Ok(())
+    }
+}
 
-            // Use helper to set it up properly
-            self.insert_new_node_at(new_idx, key, value);
+/// LFUCache split into N independently locked shards, for sharing across threads.
+/// A key always hashes to the same shard; each shard keeps its own min_freq and
+/// frequency lists, so eviction is LFU within a shard rather than across the whole cache.
+pub struct ShardedLFUCache<K, V> {
+    shards: Vec<Mutex<LFUCache<K, V>>>,
+    hasher: RandomState,
+}
 
-            return true;
+impl<K: Hash + Eq + Clone, V: Clone> ShardedLFUCache<K, V> {
+    /// Splits `capacity` as evenly as possible over `shard_count` shards.
+    /// Edge Case: capacity < shard_count -> only `capacity` shards are made; a
+    ///            zero-capacity shard would silently never cache its keys.
+    pub fn new(capacity: usize, shard_count: usize) -> Self {
+        assert!(shard_count > 0, "need at least one shard");
+        let shard_count = shard_count.min(capacity.max(1));
+        let shards = (0..shard_count)
+            .map(|i| {
+                // First capacity % shard_count shards take one extra slot
+                let share = capacity / shard_count + usize::from(i < capacity % shard_count);
+                Mutex::new(LFUCache::new(share))
+            })
+            .collect();
+        ShardedLFUCache {
+            shards,
+            hasher: RandomState::new(),
         }
+    }
 
-        // EVICTION case - cache is full
-        let evict_idx = self.evict_lfu_node();
-        self.insert_new_node_at(evict_idx, key, value);
-        true
+    fn shard<Q>(&self, key: &Q) -> &Mutex<LFUCache<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let idx = (self.hasher.hash_one(key) % self.shards.len() as u64) as usize;
+        &self.shards[idx]
     }
 
-    pub fn clear(&mut self) {
-        self.key_map.clear();
-        self.freq_heads.clear();
-        self.freq_tails.clear();
-        self.nodes.clear();
-        self.min_freq = 0;
-        println!("Cache cleared");
-    }
-    // Helper: Evict the LFU/LRU node and return its index for reuse
-fn evict_lfu_node(&mut self) -> usize {
-    // Step 1: Find the tail of min_freq list (LRU node at minimum frequency)
-    let evict_idx = *self.freq_tails.get(&self.min_freq)
-        .expect("min_freq list should have a tail");
-
-    // Step 2: Get the key to remove from key_map
-    let evict_key = self.nodes[evict_idx].key;
-
-    // Step 3: Remove from key_map
-    self.key_map.remove(&evict_key);
-
-    // Step 4: Remove from frequency list
-    self.remove_from_freq_list(evict_idx);
-
-    // Step 5: Return the index for reuse
-    evict_idx
-}
-    // Helper: Insert a new node at the given index (assumes node is already in Vec)
-    fn insert_new_node_at(&mut self, node_idx: usize, key: i32, value: i32) {
-    self.nodes[node_idx].key = key;
-    self.nodes[node_idx].val = value;
-    self.nodes[node_idx].freq = 1;
-    self.nodes[node_idx].prev = None;
-    self.nodes[node_idx].next = None;
+    /// Returns a clone: the value cannot be borrowed past the shard lock.
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let mut shard = self.shard(key).lock().expect("shard lock poisoned");
+        shard.get(key).cloned()
+    }
 
-    self.key_map.insert(key, node_idx);
-    self.add_to_freq_list(node_idx);
-    self.min_freq = 1;
-}
+    pub fn put(&self, key: K, value: V) -> Vec<(K, V)> {
+        let mut shard = self.shard(&key).lock().expect("shard lock poisoned");
+        shard.put(key, value)
+    }
 
-    pub fn display(&self) {
-        println!("Cache contents:");
-        for node in &self.nodes {
-            println!("  Key: {}, Val: {}, Freq: {}", node.key, node.val, node.freq);
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            shard.lock().expect("shard lock poisoned").clear();
         }
     }
 }
@@ -197,7 +1568,7 @@ fn main() {
 
     // 1. Initialize
     let capacity = 2;
-    let mut cache = LFUCache::new(capacity);
+    let mut cache: LFUCache<i32, i32> = LFUCache::new(capacity);
     println!("Created LFU Cache with capacity {}", capacity);
 
     // 2. Fill the cache
@@ -208,7 +1579,7 @@ fn main() {
 
     // 3. Access an element (Frequency Shift)
     println!("\n--- Step 2: Accessing key 10 (Freq 1 -> 2) ---");
-    let val = cache.get(10);
+    let val = cache.get(&10);
     println!("Got value: {:?}", val);
     cache.display();
 
@@ -216,14 +1587,16 @@ fn main() {
     println!("\n--- Step 3: Adding key 30 (Evicting LFU/LRU) ---");
     // Expectation: 20 is Freq 1. 10 is Freq 2.
     // 20 should be evicted.
-    cache.put(30, 300);
+    let evicted = cache.put(30, 300);
+    println!("Evicted: {:?}", evicted);
+    println!("Stats: {:?}", cache.stats());
     cache.display();
 
     // 5. Verify State
     println!("\n--- Final Verification ---");
-    println!("Get 20 (Should be None): {:?}", cache.get(20));
-    println!("Get 10 (Should be 100): {:?}", cache.get(10));
-    println!("Get 30 (Should be 300): {:?}", cache.get(30));
+    println!("Get 20 (Should be None): {:?}", cache.get(&20));
+    println!("Get 10 (Should be 100): {:?}", cache.get(&10));
+    println!("Get 30 (Should be 300): {:?}", cache.get(&30));
 
     // 6. Further Test: Tie-breaking
     println!("\n--- Step 4: Tie-breaking Test ---");
@@ -234,7 +1607,7 @@ fn main() {
     // 10 was accessed at Step 2.
     // 30 was added at Step 3 (Freq 1).
     // Let's access 30 now.
-    cache.get(30); // 30 -> Freq 2.
+    cache.get(&30); // 30 -> Freq 2.
     // Now 10 (Freq 2, LRU relative to 30?), 30 (Freq 2, MRU)
     // Actually, in my implementation:
     // add_to_freq_list adds to HEAD.
@@ -253,7 +1626,1157 @@ fn main() {
     cache.put(40, 400);
     cache.display();
 
-    println!("Get 10 (Should be None): {:?}", cache.get(10));
-    println!("Get 30 (Should be 300): {:?}", cache.get(30));
-    println!("Get 40 (Should be 400): {:?}", cache.get(40));
+    println!("Get 10 (Should be None): {:?}", cache.get(&10));
+    println!("Get 30 (Should be 300): {:?}", cache.get(&30));
+    println!("Get 40 (Should be 400): {:?}", cache.get(&40));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Same scenario as main(): 20 is the only Freq 1 entry when 30 arrives
+    #[test]
+    fn test_evicts_least_frequent() {
+        let mut cache = LFUCache::new(2);
+        cache.put(10, 100);
+        cache.put(20, 200);
+        assert_eq!(cache.get(&10), Some(&100));
+        cache.put(30, 300);
+        assert_eq!(cache.get(&20), None);
+        assert_eq!(cache.get(&10), Some(&100));
+        assert_eq!(cache.get(&30), Some(&300));
+    }
+
+    // Tie at Freq 2: the tail (least recently used) of the list goes first
+    #[test]
+    fn test_tie_breaks_by_recency() {
+        let mut cache = LFUCache::new(2);
+        cache.put(10, 100);
+        cache.put(20, 200);
+        cache.get(&10);
+        cache.get(&20);
+        cache.put(30, 300);
+        assert_eq!(cache.get(&10), None);
+        assert_eq!(cache.get(&20), Some(&200));
+    }
+
+    #[test]
+    fn test_string_keys_and_struct_values() {
+        #[derive(Debug, PartialEq)]
+        struct Record {
+            ip: [u8; 4],
+            ttl_secs: u32,
+        }
+
+        let mut cache: LFUCache<String, Record> = LFUCache::new(1);
+        cache.put("a.com".to_string(), Record { ip: [1, 2, 3, 4], ttl_secs: 60 });
+        assert_eq!(
+            cache.get("a.com"),
+            Some(&Record { ip: [1, 2, 3, 4], ttl_secs: 60 })
+        );
+        cache.put("b.com".to_string(), Record { ip: [5, 6, 7, 8], ttl_secs: 30 });
+        assert_eq!(cache.get("a.com"), None);
+    }
+
+    #[test]
+    fn test_byte_buffer_values_update_in_place() {
+        let mut cache: LFUCache<u64, Vec<u8>> = LFUCache::new(2);
+        cache.put(1, vec![0xde, 0xad]);
+        cache.put(1, vec![0xbe, 0xef, 0x00]);
+        assert_eq!(cache.get(&1).map(|v| v.len()), Some(3));
+    }
+
+    #[test]
+    fn test_zero_capacity_rejects_put() {
+        let mut cache: LFUCache<&str, i32> = LFUCache::new(0);
+        assert_eq!(cache.put("k", 1), vec![("k", 1)]);
+        assert_eq!(cache.get(&"k"), None);
+    }
+
+    #[test]
+    fn test_entry_expires_after_ttl() {
+        let clock = ManualClock::new();
+        let mut cache = LFUCache::new(2)
+            .with_ttl(Duration::from_secs(60))
+            .with_clock(clock.clone());
+        cache.put("dns:a.com", [1, 2, 3, 4]);
+
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(cache.get(&"dns:a.com"), Some(&[1, 2, 3, 4]));
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(cache.get(&"dns:a.com"), None);
+        // get() dropped it for good; it does not come back
+        assert_eq!(cache.get(&"dns:a.com"), None);
+    }
+
+    // Live 10 sits at Freq 1 (the LFU/LRU victim), expired 20 at Freq 3.
+    // Adding 30 must reclaim 20, not evict 10.
+    #[test]
+    fn test_expired_entry_reclaimed_before_live_lfu_victim() {
+        let clock = ManualClock::new();
+        let mut cache = LFUCache::new(2).with_clock(clock.clone());
+        cache.put_with_ttl(20, 200, Duration::from_secs(5));
+        cache.get(&20);
+        cache.get(&20);
+        cache.put(10, 100);
+
+        clock.advance(Duration::from_secs(5));
+        cache.put(30, 300);
+        assert_eq!(cache.get(&10), Some(&100));
+        assert_eq!(cache.get(&30), Some(&300));
+        assert_eq!(cache.get(&20), None);
+    }
+
+    #[test]
+    fn test_update_refreshes_ttl() {
+        let clock = ManualClock::new();
+        let mut cache = LFUCache::new(2)
+            .with_ttl(Duration::from_secs(10))
+            .with_clock(clock.clone());
+        cache.put(1, "v1");
+        clock.advance(Duration::from_secs(8));
+        cache.put(1, "v2");
+        clock.advance(Duration::from_secs(8));
+        assert_eq!(cache.get(&1), Some(&"v2"));
+    }
+
+    #[test]
+    fn test_put_over_expired_key_starts_fresh() {
+        let clock = ManualClock::new();
+        let mut cache = LFUCache::new(2).with_clock(clock.clone());
+        cache.put_with_ttl(1, 10, Duration::from_secs(1));
+        cache.get(&1);
+        cache.get(&1);
+        clock.advance(Duration::from_secs(1));
+
+        // 1 comes back at Freq 1, so it is the LRU victim when 3 arrives
+        cache.put(1, 11);
+        cache.put(2, 20);
+        cache.get(&2);
+        cache.put(3, 30);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some(&20));
+    }
+
+    #[test]
+    fn test_purge_expired_frees_slots() {
+        let clock = ManualClock::new();
+        let mut cache = LFUCache::new(3).with_clock(clock.clone());
+        cache.put_with_ttl(1, 10, Duration::from_secs(1));
+        cache.put_with_ttl(2, 20, Duration::from_secs(2));
+        cache.put(3, 30);
+
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(cache.purge_expired(), 2);
+        cache.put(4, 40);
+        cache.put(5, 50);
+        // Freed slots were reused: nothing live was evicted
+        assert_eq!(cache.get(&3), Some(&30));
+        assert_eq!(cache.get(&4), Some(&40));
+        assert_eq!(cache.get(&5), Some(&50));
+        assert!(cache.key_map.values().all(|&idx| idx < 3));
+    }
+
+    #[test]
+    fn test_min_freq_repaired_after_expired_get() {
+        let clock = ManualClock::new();
+        let mut cache = LFUCache::new(2).with_clock(clock.clone());
+        cache.put_with_ttl(1, 10, Duration::from_secs(1));
+        cache.put(2, 20);
+        cache.get(&2);
+        clock.advance(Duration::from_secs(1));
+
+        // Dropping 1 empties the Freq 1 list; min_freq must move to 2
+        assert_eq!(cache.get(&1), None);
+        cache.put(3, 30);
+        cache.put(4, 40);
+        assert_eq!(cache.get(&2), Some(&20));
+        assert_eq!(cache.get(&3), None);
+    }
+
+    // Budget 10: two 4-byte blobs fit, a third forces the LFU one out
+    #[test]
+    fn test_weighted_evicts_until_budget_fits() {
+        let mut cache: LFUCache<&str, Vec<u8>> = LFUCache::weighted(10)
+            .with_weigher(|_, blob: &Vec<u8>| blob.len());
+        cache.put("a", vec![0; 4]);
+        cache.put("b", vec![0; 4]);
+        cache.get(&"b");
+        cache.put("c", vec![0; 4]);
+        assert_eq!(cache.get(&"a"), None);
+        assert!(cache.get(&"b").is_some());
+        assert_eq!(cache.total_weight(), 8);
+    }
+
+    // One big blob pushes out several small entries, lowest frequency first
+    #[test]
+    fn test_weighted_large_entry_evicts_many() {
+        let mut cache = LFUCache::weighted(10);
+        for key in 0..5 {
+            cache.put_weighted(key, key * 10, 2);
+        }
+        cache.get(&4);
+        let evicted = cache.put_weighted(99, 990, 8);
+        assert_eq!(evicted, vec![(0, 0), (1, 10), (2, 20), (3, 30)]);
+        assert_eq!(cache.get(&4), Some(&40));
+        assert_eq!(cache.get(&99), Some(&990));
+        for key in 0..4 {
+            assert_eq!(cache.get(&key), None);
+        }
+        assert_eq!(cache.total_weight(), 10);
+    }
+
+    #[test]
+    fn test_weighted_rejects_entry_over_budget() {
+        let mut cache = LFUCache::weighted(5);
+        cache.put_weighted("small", 1, 2);
+        assert_eq!(cache.put_weighted("huge", 2, 6), vec![("huge", 2)]);
+        assert_eq!(cache.get(&"huge"), None);
+        assert_eq!(cache.get(&"small"), Some(&1));
+
+        // Oversized update drops the old value rather than keep serving it
+        assert_eq!(cache.put_weighted("small", 3, 6), vec![("small", 3)]);
+        assert_eq!(cache.get(&"small"), None);
+        assert_eq!(cache.total_weight(), 0);
+    }
+
+    // "a" is the only Freq 1 entry; growing it must evict "b", never "a" itself
+    #[test]
+    fn test_weighted_update_grows_without_self_eviction() {
+        let mut cache = LFUCache::weighted(10);
+        cache.put_weighted("a", 1, 3);
+        cache.put_weighted("b", 2, 5);
+        cache.get(&"b");
+        cache.get(&"b");
+        assert_eq!(cache.put_weighted("a", 11, 7), vec![("b", 2)]);
+        assert_eq!(cache.get(&"a"), Some(&11));
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(cache.total_weight(), 7);
+    }
+
+    #[test]
+    fn test_put_returns_evicted_pair() {
+        let mut cache = LFUCache::new(2);
+        assert!(cache.put(10, 100).is_empty());
+        assert!(cache.put(20, 200).is_empty());
+        cache.get(&10);
+        assert_eq!(cache.put(30, 300), vec![(20, 200)]);
+        // Update of a live key evicts nothing
+        assert!(cache.put(30, 301).is_empty());
+    }
+
+    #[test]
+    fn test_eviction_listener_reports_cause() {
+        use std::sync::Mutex;
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        let clock = ManualClock::new();
+        let mut cache = LFUCache::new(3)
+            .with_clock(clock.clone())
+            .with_eviction_listener(move |k: &&str, v: &i32, cause| {
+                sink.lock().unwrap().push((*k, *v, cause));
+            });
+
+        cache.put("a", 1);
+        cache.put("a", 2);
+        cache.put_with_ttl("b", 3, Duration::from_secs(1));
+        cache.get(&"b");
+        cache.get(&"b");
+        cache.put("c", 4);
+        clock.advance(Duration::from_secs(1));
+        // Full: expired "b" (Freq 3) goes before live "c" (Freq 1)
+        cache.put("d", 5);
+        cache.put("e", 6);
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                ("a", 1, EvictionCause::Replaced),
+                ("b", 3, EvictionCause::Expired),
+                ("c", 4, EvictionCause::Capacity),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_listener_sees_expired_entries() {
+        use std::sync::Mutex;
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        let clock = ManualClock::new();
+        let mut cache = LFUCache::new(4)
+            .with_ttl(Duration::from_secs(1))
+            .with_clock(clock.clone())
+            .with_eviction_listener(move |k: &i32, _: &i32, cause| {
+                sink.lock().unwrap().push((*k, cause));
+            });
+        cache.put(1, 10);
+        cache.put(2, 20);
+        clock.advance(Duration::from_secs(1));
+
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.purge_expired(), 1);
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![(1, EvictionCause::Expired), (2, EvictionCause::Expired)]
+        );
+    }
+
+    #[test]
+    fn test_sharded_capacity_split() {
+        let cache: ShardedLFUCache<i32, i32> = ShardedLFUCache::new(10, 4);
+        let shares: Vec<usize> = cache.shards.iter().map(|s| s.lock().unwrap().capacity).collect();
+        assert_eq!(shares, vec![3, 3, 2, 2]);
+    }
+
+    #[test]
+    fn test_sharded_never_makes_empty_shards() {
+        let cache = ShardedLFUCache::new(2, 8);
+        let shares: Vec<usize> = cache.shards.iter().map(|s| s.lock().unwrap().capacity).collect();
+        assert_eq!(shares, vec![1, 1]);
+        cache.put("a", 1);
+        cache.put("b", 2);
+        assert!(cache.get(&"a").is_some() || cache.get(&"b").is_some());
+    }
+
+    #[test]
+    fn test_sharded_get_put_clear() {
+        let cache = ShardedLFUCache::new(64, 4);
+        for key in 0..16 {
+            cache.put(key, key * 100);
+        }
+        for key in 0..16 {
+            assert_eq!(cache.get(&key), Some(key * 100));
+        }
+        cache.clear();
+        assert_eq!(cache.get(&3), None);
+    }
+
+    // 8 threads hammer one cache at once. Each key only ever maps to key * 7,
+    // so any other value seen means a shard was corrupted.
+    #[test]
+    fn test_sharded_concurrent_stress() {
+        use std::thread;
+
+        let capacity = 128;
+        let cache = Arc::new(ShardedLFUCache::new(capacity, 8));
+        let handles: Vec<_> = (0..8u64)
+            .map(|t| {
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || {
+                    let mut hits = 0;
+                    for i in 0..20_000u64 {
+                        let key = (i * 31 + t * 17) % 512;
+                        if i % 3 == 0 {
+                            cache.put(key, key * 7);
+                        } else if let Some(val) = cache.get(&key) {
+                            assert_eq!(val, key * 7);
+                            hits += 1;
+                        }
+                    }
+                    hits
+                })
+            })
+            .collect();
+        let hits: u64 = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        assert!(hits > 0);
+
+        let mut total = 0;
+        for shard in &cache.shards {
+            let shard = shard.lock().unwrap();
+            assert!(shard.key_map.len() <= shard.capacity);
+            total += shard.key_map.len();
+        }
+        assert!(total <= capacity);
+    }
+
+    // Walks the bucket list and every frequency list and checks them against key_map
+    fn assert_consistent<K: Hash + Eq + Clone, V>(cache: &LFUCache<K, V>) {
+        let mut seen = 0;
+        let (mut pinned, mut pinned_weight) = (0, 0);
+        let mut live_buckets = 0;
+        let mut prev_bucket: Option<usize> = None;
+        let mut bucket_cursor = cache.first_bucket;
+        while let Some(bucket_idx) = bucket_cursor {
+            let bucket = &cache.buckets[bucket_idx];
+            assert_eq!(bucket.prev, prev_bucket);
+            if let Some(prev_idx) = prev_bucket {
+                assert!(cache.buckets[prev_idx].freq < bucket.freq);
+            }
+            let freq = bucket.freq;
+            let mut prev = None;
+            let mut count = 0;
+            let mut cursor = cache.slab.front(bucket_idx);
+            while let Some(idx) = cursor {
+                let node = cache.node(idx);
+                assert_eq!(node.freq, freq);
+                assert_eq!(cache.slab.list_of(idx), bucket_idx);
+                assert_eq!(cache.slab.prev(idx), prev);
+                assert_eq!(cache.key_map.get(&node.key), Some(&idx));
+                seen += 1;
+                count += 1;
+                if node.pinned {
+                    pinned += 1;
+                    pinned_weight += node.weight;
+                }
+                prev = Some(idx);
+                cursor = cache.slab.next(idx);
+            }
+            assert!(count > 0, "buckets are freed when they empty");
+            assert_eq!(cache.slab.back(bucket_idx), prev);
+            assert_eq!(cache.slab.list_len(bucket_idx), count);
+            live_buckets += 1;
+            prev_bucket = Some(bucket_idx);
+            bucket_cursor = bucket.next;
+        }
+        assert_eq!(seen, cache.key_map.len());
+        assert_eq!((pinned, pinned_weight), (cache.pinned_count, cache.pinned_weight));
+        assert_eq!(seen, cache.slab.iter().count());
+        // Every open slab list is a linked bucket
+        assert_eq!(live_buckets, cache.slab.list_count());
+    }
+
+    // "hot" gets 50 hits up front, then only cold keys arrive, each read once.
+    // Without aging "hot" (Freq 51) outranks every newcomer (Freq 2) forever.
+    fn evicts_formerly_hot_key(aging_period: usize) -> bool {
+        let mut cache = LFUCache::new(2).with_aging(aging_period);
+        cache.put("hot".to_string(), 0);
+        for _ in 0..50 {
+            cache.get("hot");
+        }
+        for i in 0..200 {
+            let key = format!("cold{}", i);
+            let evicted = cache.put(key.clone(), i);
+            cache.get(&key);
+            assert_consistent(&cache);
+            if evicted.iter().any(|(k, _)| k == "hot") {
+                return true;
+            }
+        }
+        false
+    }
+
+    #[test]
+    fn test_aging_evicts_formerly_hot_key() {
+        assert!(evicts_formerly_hot_key(8));
+    }
+
+    #[test]
+    fn test_without_aging_hot_key_never_leaves() {
+        assert!(!evicts_formerly_hot_key(0));
+    }
+
+    #[test]
+    fn test_halving_merges_lists_and_keeps_order() {
+        let mut cache = LFUCache::new(4).with_aging(1_000);
+        cache.put(1, ()); // Freq 1
+        cache.put(2, ());
+        cache.get(&2); // Freq 2
+        cache.put(3, ());
+        cache.get(&3);
+        cache.get(&3); // Freq 3
+        cache.put(4, ());
+        cache.get(&4);
+        cache.get(&4);
+        cache.get(&4); // Freq 4
+
+        cache.halve_frequencies();
+        assert_consistent(&cache);
+        let freqs: Vec<usize> = (1..=4).map(|k| cache.node(cache.key_map[&k]).freq).collect();
+        assert_eq!(freqs, vec![1, 1, 1, 2]);
+        // Merged Freq 1 list, MRU -> LRU: 3 (was 3), 2 (was 2), 1 (was 1)
+        let first = cache.first_bucket.unwrap();
+        assert_eq!(cache.buckets[first].freq, 1);
+        assert_eq!(cache.node(cache.slab.front(first).unwrap()).key, 3);
+        assert_eq!(cache.node(cache.slab.back(first).unwrap()).key, 1);
+    }
+
+    // "c" is the LRU victim. Every scan key is seen once, so none of them
+    // looks more popular than "c" and the scan never gets in.
+    #[test]
+    fn test_admission_filter_blocks_one_hit_scan() {
+        let mut cache = LFUCache::new(3).with_admission_filter();
+        cache.put("a".to_string(), 1);
+        cache.put("b".to_string(), 2);
+        cache.put("c".to_string(), 3);
+        for _ in 0..4 {
+            cache.get("a");
+            cache.get("b");
+            cache.get("c");
+        }
+
+        for i in 0..100 {
+            let key = format!("scan{}", i);
+            assert_eq!(cache.put(key.clone(), i), vec![(key, i)]);
+        }
+        assert_eq!(cache.get("c"), Some(&3));
+    }
+
+    #[test]
+    fn test_without_admission_filter_scan_displaces_entries() {
+        let mut cache = LFUCache::new(3);
+        cache.put("a".to_string(), 1);
+        cache.put("b".to_string(), 2);
+        cache.put("c".to_string(), 3);
+        for i in 0..100 {
+            cache.put(format!("scan{}", i), i);
+        }
+        assert_eq!(cache.get("c"), None);
+    }
+
+    // Repeated misses on "x" raise its estimate above the victim's, so it is admitted
+    #[test]
+    fn test_admission_filter_admits_frequently_requested_key() {
+        let mut cache = LFUCache::new(2).with_admission_filter();
+        cache.put("a", 1);
+        cache.put("b", 2);
+        cache.get(&"a");
+        for _ in 0..3 {
+            assert_eq!(cache.get(&"x"), None);
+        }
+        assert_eq!(cache.put("x", 9), vec![("b", 2)]);
+        assert_eq!(cache.get(&"x"), Some(&9));
+    }
+
+    #[test]
+    fn test_admission_filter_never_blocks_updates() {
+        let mut cache = LFUCache::new(1).with_admission_filter();
+        cache.put(1, 10);
+        assert!(cache.put(1, 11).is_empty());
+        assert_eq!(cache.get(&1), Some(&11));
+    }
+
+    #[test]
+    fn test_sketch_counts_saturate_and_reset() {
+        let mut sketch = FrequencySketch::new(0);
+        for _ in 0..20 {
+            sketch.increment(&"k");
+        }
+        assert_eq!(sketch.estimate(&"k"), 15);
+        assert_eq!(sketch.estimate(&"never"), 0);
+
+        // sample_size = 10 * 256 increments in total triggers one halving
+        for i in 0..2540 {
+            sketch.increment(&i);
+        }
+        assert_eq!(sketch.estimate(&"k"), 7);
+    }
+
+    #[test]
+    fn test_stats_counts_and_hit_ratio() {
+        let mut cache = LFUCache::new(2);
+        assert_eq!(cache.stats().hit_ratio(), 0.0);
+
+        cache.put(1, 10);
+        cache.put(2, 20);
+        cache.put(2, 21); // 2 -> Freq 2
+        cache.get(&1);
+        cache.get(&1); // 1 -> Freq 3
+        cache.get(&2); // 2 -> Freq 3, MRU
+        cache.get(&9);
+        cache.put(3, 30); // evicts 1, the LRU at Freq 3
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (3, 1));
+        assert_eq!((stats.inserts, stats.updates, stats.evictions), (3, 1, 1));
+        assert_eq!(stats.hit_ratio(), 0.75);
+        assert_eq!(stats.len, 2);
+        assert_eq!(stats.min_freq, 1);
+        assert_eq!(stats.freq_histogram, vec![(1, 1), (3, 1)]);
+
+        cache.reset_stats();
+        assert_eq!(cache.stats().hits, 0);
+        assert_eq!(cache.stats().len, 2);
+    }
+
+    #[test]
+    fn test_stats_counts_expirations_and_rejections() {
+        let clock = ManualClock::new();
+        let mut cache = LFUCache::weighted(4).with_clock(clock.clone());
+        cache.put_with_ttl("a", 1, Duration::from_secs(1));
+        cache.put_weighted("big", 2, 5);
+        clock.advance(Duration::from_secs(1));
+        cache.get(&"a");
+
+        let stats = cache.stats();
+        assert_eq!((stats.expirations, stats.rejections, stats.misses), (1, 1, 1));
+        assert_eq!(stats.evictions, 0);
+        assert!(stats.freq_histogram.is_empty());
+    }
+
+    fn snapshot_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("lfu_{}_{}.snap", name, std::process::id()))
+    }
+
+    // Keys 1..=5 at mixed frequencies. After a round trip both caches must hand
+    // out the same victims in the same order.
+    #[test]
+    fn test_snapshot_round_trip_keeps_eviction_order() {
+        let path = snapshot_path("round_trip");
+        let mut original: LFUCache<String, Vec<u8>> = LFUCache::new(5);
+        for key in 1..=5u8 {
+            original.put(format!("k{}", key), vec![key; key as usize]);
+        }
+        for key in [3, 1, 3, 5, 1, 3] {
+            original.get(&format!("k{}", key));
+        }
+        original.save_snapshot(&path).unwrap();
+
+        let mut restored: LFUCache<String, Vec<u8>> = LFUCache::new(1);
+        restored.load_snapshot(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_consistent(&restored);
+        assert_eq!(restored.capacity, 5);
+        assert_eq!(restored.min_freq(), original.min_freq());
+        assert_eq!(restored.stats().freq_histogram, original.stats().freq_histogram);
+
+        for i in 0..5 {
+            let key = format!("new{}", i);
+            assert_eq!(original.put(key.clone(), vec![]), restored.put(key, vec![]));
+        }
+    }
+
+    #[test]
+    fn test_snapshot_keeps_remaining_ttl_and_weight() {
+        let path = snapshot_path("ttl");
+        let clock = ManualClock::new();
+        let mut original = LFUCache::weighted(10).with_clock(clock.clone());
+        original.put_with_ttl(1u32, 10i64, Duration::from_secs(10));
+        original.put_weighted(2u32, 20i64, 4);
+        clock.advance(Duration::from_secs(6));
+        original.save_snapshot(&path).unwrap();
+
+        let clock = ManualClock::new();
+        let mut restored: LFUCache<u32, i64> = LFUCache::new(1).with_clock(clock.clone());
+        restored.load_snapshot(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(restored.total_weight(), 5);
+
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(restored.get(&1), Some(&10));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(restored.get(&1), None);
+        assert_eq!(restored.get(&2), Some(&20));
+    }
+
+    #[test]
+    fn test_snapshot_temp_file_is_per_file_name() {
+        let snap = snapshot_path("temp_name");
+        let bak = snap.with_extension("bak");
+        let tmp = snap.with_extension("tmp");
+        assert_eq!(temp_path(&snap).unwrap(), snap.with_extension("snap.tmp"));
+        assert_ne!(temp_path(&snap).unwrap(), temp_path(&bak).unwrap());
+        assert!(temp_path(Path::new("/")).is_err());
+
+        // Saving to a .tmp file must not write its temp file onto itself
+        let mut cache: LFUCache<i32, i32> = LFUCache::new(2);
+        cache.put(1, 10);
+        cache.save_snapshot(&tmp).unwrap();
+        let mut restored: LFUCache<i32, i32> = LFUCache::new(1);
+        restored.load_snapshot(&tmp).unwrap();
+        fs::remove_file(&tmp).unwrap();
+        assert!(!temp_path(&tmp).unwrap().exists());
+        assert_eq!(restored.get(&1), Some(&10));
+    }
+
+    #[test]
+    fn test_snapshot_rejects_corrupt_files() {
+        let path = snapshot_path("corrupt");
+        let mut cache: LFUCache<i32, i32> = LFUCache::new(2);
+        cache.put(1, 10);
+        cache.save_snapshot(&path).unwrap();
+        let good = fs::read(&path).unwrap();
+
+        fs::write(&path, &good[..good.len() - 1]).unwrap();
+        assert_eq!(cache.load_snapshot(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let mut bad_magic = good.clone();
+        bad_magic[0] = b'X';
+        fs::write(&path, &bad_magic).unwrap();
+        assert!(cache.load_snapshot(&path).is_err());
+
+        let mut bad_version = good;
+        bad_version[4] = 99;
+        fs::write(&path, &bad_version).unwrap();
+        assert!(cache.load_snapshot(&path).is_err());
+        fs::remove_file(&path).unwrap();
+
+        // A failed load leaves the cache untouched
+        assert_eq!(cache.get(&1), Some(&10));
+    }
+
+    #[test]
+    fn test_peek_does_not_change_eviction_order() {
+        let mut cache = LFUCache::new(2);
+        cache.put(1, 10);
+        cache.put(2, 20);
+        for _ in 0..5 {
+            assert_eq!(cache.peek(&1), Some(&10));
+        }
+        // 1 is still the LRU at Freq 1
+        assert_eq!(cache.put(3, 30), vec![(1, 10)]);
+        assert_eq!(cache.peek(&1), None);
+        assert_eq!(cache.stats().hits, 0);
+    }
+
+    #[test]
+    fn test_len_contains_and_capacity() {
+        let clock = ManualClock::new();
+        let mut cache = LFUCache::new(3).with_clock(clock.clone());
+        assert!(cache.is_empty());
+        assert_eq!(cache.capacity(), 3);
+        cache.put("a", 1);
+        cache.put_with_ttl("b", 2, Duration::from_secs(1));
+        assert_eq!(cache.len(), 2);
+        assert!(cache.contains_key(&"b"));
+
+        clock.advance(Duration::from_secs(1));
+        assert!(!cache.contains_key(&"b"));
+        assert!(!cache.contains_key(&"zzz"));
+        assert_eq!(cache.len(), 2);
+        cache.purge_expired();
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_iter_yields_eviction_order() {
+        let mut cache = LFUCache::new(5);
+        for key in 1..=5 {
+            cache.put(key, key * 10);
+        }
+        cache.get(&4);
+        cache.get(&2);
+        cache.get(&2);
+        cache.get(&5);
+        // Freq 1: 1, 3 (LRU first). Freq 2: 4, 5. Freq 3: 2.
+        let keys: Vec<i32> = cache.iter().map(|(k, _)| *k).collect();
+        assert_eq!(keys, vec![1, 3, 4, 5, 2]);
+
+        // iter() order is exactly the order put() evicts in
+        let mut evicted = Vec::new();
+        for key in 100..105 {
+            for (k, _) in cache.put(key, 0) {
+                evicted.push(k);
+            }
+            cache.get(&key);
+            cache.get(&key);
+            cache.get(&key);
+        }
+        assert_eq!(evicted, keys);
+    }
+
+    #[test]
+    fn test_remove_returns_value_and_reuses_slot() {
+        let mut cache = LFUCache::new(3);
+        cache.put("a", 1);
+        cache.put("b", 2);
+        cache.put("c", 3);
+        assert_eq!(cache.remove(&"b"), Some(2));
+        assert_eq!(cache.remove(&"b"), None);
+        assert_eq!(cache.len(), 2);
+        assert_consistent(&cache);
+
+        // Room for "d" without evicting, and it lands in b's old slot
+        assert!(cache.put("d", 4).is_empty());
+        assert!(cache.key_map.values().all(|&idx| idx < 3));
+        assert_eq!(cache.key_map[&"d"], 1);
+        assert_eq!(cache.stats().removals, 1);
+    }
+
+    // "a" is alone at Freq 1. Removing it must move min_freq to 3, not 2,
+    // or the next eviction would look for a list that does not exist.
+    #[test]
+    fn test_remove_keeps_min_freq_correct() {
+        let mut cache = LFUCache::new(2);
+        cache.put("a", 1);
+        cache.put("b", 2);
+        cache.get(&"b");
+        cache.get(&"b");
+        assert_eq!(cache.remove(&"a"), Some(1));
+        assert_eq!(cache.min_freq(), 3);
+        assert_consistent(&cache);
+
+        cache.put("c", 3);
+        assert_eq!(cache.put("d", 4), vec![("c", 3)]);
+        assert_consistent(&cache);
+    }
+
+    #[test]
+    fn test_remove_notifies_listener_as_explicit() {
+        use std::sync::Mutex;
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        let mut cache = LFUCache::new(2).with_eviction_listener(move |k: &i32, v: &i32, cause| {
+            sink.lock().unwrap().push((*k, *v, cause));
+        });
+        cache.put(1, 10);
+        cache.remove(&1);
+        assert_eq!(*log.lock().unwrap(), vec![(1, 10, EvictionCause::Explicit)]);
+    }
+
+    #[test]
+    fn test_set_capacity_shrink_evicts_in_lfu_order() {
+        let mut cache = LFUCache::new(4);
+        for key in 1..=4 {
+            cache.put(key, key * 10);
+        }
+        cache.get(&1);
+        cache.get(&3);
+        cache.get(&3);
+        // Freq 1: 2, 4 (LRU first). Freq 2: 1. Freq 3: 3.
+        assert_eq!(cache.set_capacity(1), vec![(2, 20), (4, 40), (1, 10)]);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.min_freq(), 3);
+        assert_consistent(&cache);
+        assert_eq!(cache.put(5, 50), vec![(3, 30)]);
+    }
+
+    #[test]
+    fn test_set_capacity_grow_takes_effect_at_once() {
+        let mut cache = LFUCache::new(1);
+        cache.put(1, 10);
+        assert!(cache.set_capacity(3).is_empty());
+        assert!(cache.put(2, 20).is_empty());
+        assert!(cache.put(3, 30).is_empty());
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.put(4, 40).len(), 1);
+    }
+
+    #[test]
+    fn test_set_capacity_to_zero_empties_cache() {
+        let mut cache = LFUCache::new(2);
+        cache.put(1, 10);
+        cache.put(2, 20);
+        cache.get(&2);
+        assert_eq!(cache.set_capacity(0).len(), 2);
+        assert!(cache.is_empty());
+        assert_eq!(cache.min_freq(), 0);
+        assert_eq!(cache.put(3, 30), vec![(3, 30)]);
+
+        cache.set_capacity(1);
+        assert!(cache.put(3, 30).is_empty());
+        assert_eq!(cache.get(&3), Some(&30));
+    }
+
+    #[test]
+    fn test_set_max_weight_shrinks_weighted_cache() {
+        let mut cache = LFUCache::weighted(10);
+        cache.put_weighted("a", 1, 4);
+        cache.put_weighted("b", 2, 4);
+        cache.get(&"a");
+        assert_eq!(cache.set_max_weight(5), vec![("b", 2)]);
+        assert_eq!(cache.total_weight(), 4);
+        assert_consistent(&cache);
+    }
+
+    #[test]
+    fn test_get_or_insert_with_loads_once() {
+        let mut cache = LFUCache::new(2);
+        let mut loads = 0;
+        for _ in 0..3 {
+            let value = cache.get_or_insert_with("k", || {
+                loads += 1;
+                42
+            });
+            assert_eq!(value, Some(&42));
+        }
+        assert_eq!(loads, 1);
+        // Miss then two hits: Freq 1 on insert, +1 per hit
+        assert_eq!(cache.node(cache.key_map[&"k"]).freq, 3);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.inserts), (2, 1, 1));
+    }
+
+    // get() + put() on a miss feeds the admission sketch twice for one request;
+    // get_or_insert_with() feeds it once
+    #[test]
+    fn test_get_or_insert_with_counts_one_access() {
+        let mut manual = LFUCache::new(4).with_admission_filter();
+        if manual.get(&"k").is_none() {
+            manual.put("k", 1);
+        }
+        let mut loaded = LFUCache::new(4).with_admission_filter();
+        loaded.get_or_insert_with("k", || 1);
+
+        let estimate = |cache: &LFUCache<&str, i32>| cache.admission.as_ref().unwrap().estimate(&"k");
+        assert_eq!(estimate(&manual), 2);
+        assert_eq!(estimate(&loaded), 1);
+    }
+
+    #[test]
+    fn test_try_get_or_insert_with_error_caches_nothing() {
+        let mut cache: LFUCache<&str, u32> = LFUCache::new(2);
+        let result: Result<Option<&u32>, String> =
+            cache.try_get_or_insert_with("port", || "not a number".parse().map_err(|_| "bad".to_string()));
+        assert_eq!(result, Err("bad".to_string()));
+        assert!(!cache.contains_key(&"port"));
+
+        let result: Result<Option<&u32>, String> = cache.try_get_or_insert_with("port", || Ok(8080));
+        assert_eq!(result, Ok(Some(&8080)));
+    }
+
+    #[test]
+    fn test_get_or_insert_with_refused_value() {
+        let mut cache = LFUCache::new(0);
+        assert_eq!(cache.get_or_insert_with(1, || 10), None);
+    }
+
+    #[test]
+    fn test_memoized_runs_function_once_per_argument() {
+        let mut calls = 0;
+        let mut factorial = Memoized::new(8, |n: &u64| {
+            calls += 1;
+            (1..=*n).product::<u64>()
+        });
+        assert_eq!(factorial.call(5), 120);
+        assert_eq!(factorial.call(5), 120);
+        assert_eq!(factorial.call(6), 720);
+        assert_eq!(factorial.cache().stats().hits, 1);
+        drop(factorial);
+        assert_eq!(calls, 2);
+
+        // A cache that keeps nothing still returns the computed result
+        let mut uncached = Memoized::new(0, |s: &String| s.len());
+        assert_eq!(uncached.call("four".to_string()), 4);
+    }
+
+    // Counts its own clones, to check Memoized copies a result only when it must
+    struct CloneCount(std::rc::Rc<std::cell::Cell<usize>>);
+
+    impl Clone for CloneCount {
+        fn clone(&self) -> Self {
+            self.0.set(self.0.get() + 1);
+            CloneCount(self.0.clone())
+        }
+    }
+
+    #[test]
+    fn test_memoized_clones_only_results_it_keeps() {
+        let clones = std::rc::Rc::new(std::cell::Cell::new(0));
+        let counter = clones.clone();
+        let mut kept = Memoized::new(1, move |_: &u32| CloneCount(counter.clone()));
+        kept.call(1); // computed and cached: one copy handed out
+        assert_eq!(clones.get(), 1);
+        kept.call(1); // hit: one copy handed out
+        assert_eq!(clones.get(), 2);
+
+        let counter = clones.clone();
+        let mut refused = Memoized::new(0, move |_: &u32| CloneCount(counter.clone()));
+        refused.call(1); // refused by the cache: returned as is
+        assert_eq!(clones.get(), 2);
+    }
+
+    #[test]
+    fn test_entry_counter_bumps_frequency_once() {
+        let mut counts: LFUCache<&str, u32> = LFUCache::new(2);
+        for _ in 0..3 {
+            *counts.entry("a").or_insert(0).unwrap() += 1;
+        }
+        counts.entry("b").and_modify(|n| *n += 1).or_default();
+
+        assert_eq!(counts.peek(&"a"), Some(&3));
+        assert_eq!(counts.peek(&"b"), Some(&0));
+        // One access per entry() call, none extra for the write
+        assert_eq!(counts.node(counts.key_map[&"a"]).freq, 3);
+        assert_eq!(counts.node(counts.key_map[&"b"]).freq, 1);
+        let stats = counts.stats();
+        assert_eq!((stats.hits, stats.misses, stats.inserts), (2, 2, 2));
+        assert_consistent(&counts);
+    }
+
+    // Misses do not advance aging, as with get(); a hit or a vacant insert does
+    #[test]
+    fn test_entry_ticks_aging_only_on_hit_or_insert() {
+        let mut cache = LFUCache::new(4).with_aging(100);
+        cache.put("a", 1);
+        for _ in 0..5 {
+            assert!(matches!(cache.entry("x"), Entry::Vacant(_)));
+        }
+        assert_eq!(cache.accesses_since_aging, 1);
+        assert!(matches!(cache.entry("a"), Entry::Occupied(_)));
+        assert_eq!(cache.accesses_since_aging, 2);
+        cache.entry("y").or_insert(2);
+        assert_eq!(cache.accesses_since_aging, 3);
+    }
+
+    #[test]
+    fn test_entry_and_modify_skips_vacant() {
+        let mut cache = LFUCache::new(2);
+        let mut called = false;
+        let value = cache.entry(1).and_modify(|_| called = true).or_insert_with(|| 10);
+        assert_eq!(value, Some(&mut 10));
+        assert!(!called);
+        let value = cache.entry(1).and_modify(|v| *v *= 2).or_insert_with(|| unreachable!());
+        assert_eq!(value, Some(&mut 20));
+    }
+
+    #[test]
+    fn test_entry_vacant_insert_evicts_lfu() {
+        let mut cache = LFUCache::new(2);
+        cache.put(1, "one");
+        cache.put(2, "two");
+        cache.get(&1);
+        assert_eq!(cache.entry(3).or_insert("three"), Some(&mut "three"));
+        assert!(!cache.contains_key(&2));
+        assert_eq!(cache.stats().evictions, 1);
+        assert_consistent(&cache);
+
+        let mut refused = LFUCache::new(0);
+        assert_eq!(refused.entry(1).or_insert("one"), None);
+        assert_eq!(refused.stats().rejections, 1);
+    }
+
+    #[test]
+    fn test_occupied_entry_insert_and_remove() {
+        let mut cache = LFUCache::new(2);
+        cache.put("k", 1);
+        match cache.entry("k") {
+            Entry::Occupied(mut entry) => {
+                assert_eq!(entry.insert(2), 1);
+                assert_eq!(entry.remove(), 2);
+            }
+            Entry::Vacant(_) => panic!("k should be cached"),
+        }
+        assert!(cache.is_empty());
+        assert_eq!(cache.stats().removals, 1);
+        assert_consistent(&cache);
+    }
+
+    #[test]
+    fn test_entry_expired_is_vacant() {
+        let clock = ManualClock::new();
+        let mut cache = LFUCache::new(2).with_clock(clock.clone()).with_ttl(Duration::from_secs(1));
+        cache.put("k", 1);
+        clock.advance(Duration::from_secs(2));
+        assert!(matches!(cache.entry("k"), Entry::Vacant(_)));
+        assert_eq!(cache.stats().expirations, 1);
+    }
+
+    #[test]
+    fn test_pinned_entry_is_never_evicted() {
+        let mut cache = LFUCache::new(2);
+        cache.put("config", 0);
+        assert!(cache.pin(&"config"));
+        for i in 1..10 {
+            cache.put("hot", i);
+            cache.get(&"hot");
+        }
+        // "config" is the LFU entry, so the victim has to be "hot"
+        assert_eq!(cache.put("new", 1), vec![("hot", 9)]);
+        assert!(cache.contains_key(&"config"));
+        assert_consistent(&cache);
+
+        assert!(cache.unpin(&"config"));
+        assert_eq!(cache.put("newer", 2), vec![("config", 0)]);
+        assert!(!cache.pin(&"missing"));
+    }
+
+    #[test]
+    fn test_all_pinned_is_an_error() {
+        let mut cache = LFUCache::new(2);
+        cache.put(1, "one");
+        cache.put(2, "two");
+        cache.pin(&1);
+        cache.pin(&2);
+        assert_eq!(cache.try_put(3, "three"), Err(AllPinned { key: 3, value: "three" }));
+        assert_eq!(cache.put(3, "three"), vec![(3, "three")]);
+        // Updating a pinned key needs no room
+        assert_eq!(cache.try_put(1, "uno"), Ok(Vec::new()));
+        assert_eq!(cache.stats().rejections, 2);
+        assert_eq!(cache.len(), 2);
+        assert_consistent(&cache);
+    }
+
+    #[test]
+    fn test_pins_hold_cache_over_shrunk_capacity() {
+        let mut cache = LFUCache::new(3);
+        for i in 0..3 {
+            cache.put(i, i);
+            cache.pin(&i);
+        }
+        assert!(cache.set_capacity(1).is_empty());
+        assert_eq!(cache.len(), 3);
+        cache.unpin(&0);
+        cache.unpin(&1);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.contains_key(&2));
+        assert_consistent(&cache);
+    }
+
+    #[test]
+    fn test_pinned_weight_guards_weighted_eviction() {
+        // Weight = the value itself
+        let mut cache = LFUCache::weighted(10).with_weigher(|_: &&str, v: &usize| *v);
+        cache.put("big", 8);
+        cache.put("small", 2);
+        cache.pin(&"big");
+        // Evicting "small" frees 2, not enough for 4: nothing is evicted
+        assert_eq!(cache.try_put("x", 4), Err(AllPinned { key: "x", value: 4 }));
+        assert!(cache.contains_key(&"small"));
+        assert_eq!(cache.try_put("y", 2), Ok(vec![("small", 2)]));
+        assert_eq!(cache.total_weight(), 10);
+        assert_consistent(&cache);
+    }
+
+    // A heavier update that cannot fit must leave the old value in place and unreported
+    #[test]
+    fn test_refused_heavier_update_keeps_old_value() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        let mut cache = LFUCache::weighted(10)
+            .with_weigher(|_: &&str, v: &usize| *v)
+            .with_eviction_listener(move |k: &&str, v: &usize, cause| sink.lock().unwrap().push((*k, *v, cause)));
+        cache.put("big", 8);
+        cache.put("small", 2);
+        cache.pin(&"big");
+        assert_eq!(cache.try_put("small", 3), Err(AllPinned { key: "small", value: 3 }));
+        assert_eq!(cache.peek(&"small"), Some(&2));
+        assert!(log.lock().unwrap().is_empty());
+        assert_eq!(cache.stats().updates, 0);
+        assert_eq!(cache.stats().rejections, 1);
+        assert_eq!(cache.total_weight(), 10);
+        assert_consistent(&cache);
+    }
+
+    #[test]
+    fn test_pinned_entry_does_not_expire() {
+        let clock = ManualClock::new();
+        let mut cache = LFUCache::new(2).with_clock(clock.clone()).with_ttl(Duration::from_secs(1));
+        cache.put("k", 1);
+        cache.pin(&"k");
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(cache.get(&"k"), Some(&1));
+        assert_eq!(cache.purge_expired(), 0);
+        cache.unpin(&"k");
+        assert_eq!(cache.get(&"k"), None);
+        assert_eq!(cache.stats().expirations, 1);
+    }
+
+    // Buckets stay sorted by freq, never empty, and freed slots are reused
+    #[test]
+    fn test_frequency_buckets_freed_and_reused() {
+        let mut cache = LFUCache::new(3);
+        cache.put(1, ());
+        cache.put(2, ());
+        cache.get(&1);
+        cache.get(&1); // buckets: Freq 1 [2], Freq 3 [1]
+        assert_eq!(cache.stats().freq_histogram, vec![(1, 1), (3, 1)]);
+        cache.get(&2); // Freq 1 empties, Freq 2 opens between nothing and Freq 3
+        assert_eq!(cache.stats().freq_histogram, vec![(2, 1), (3, 1)]);
+        assert_eq!(cache.min_freq(), 2);
+        assert_consistent(&cache);
+        cache.put(3, ());
+        cache.get(&3); // Freq 2 now holds 2 and 3; the Freq 1 bucket is freed again
+        let slots = cache.buckets.len();
+        cache.remove(&3);
+        cache.put(4, ()); // Freq 1 reopens in the freed slot
+        assert_eq!(cache.buckets.len(), slots);
+        assert_eq!(cache.stats().freq_histogram, vec![(1, 1), (2, 1), (3, 1)]);
+        assert_consistent(&cache);
+    }
 }
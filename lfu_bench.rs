@@ -0,0 +1,326 @@
+// Micro-benchmark for LFUCache's frequency bookkeeping.
+//
+// Runs the same get-then-put-on-miss workloads against LFUCache (frequency
+// buckets as slab lists, linked lowest freq first) and against HashListLFU,
+// the textbook O(1) LFU: per-frequency list ends kept in HashMap<freq, node
+// index>, and min_freq kept exact without searching (reset to 1 on insert,
+// moved up when a bump empties its list).
+//
+//   rustc -O lfu_bench.rs && ./lfu_bench [ops per run]
+//
+// HashListLFU::put hands back its victim in a Vec like LFUCache::put, so both
+// pay for the same allocation on a miss and the rows compare the layouts.
+// LFUCache still runs its TTL, pin, weight, admission and listener checks; with
+// those features off they cost nothing measurable. Rounds alternate between
+// the two layouts; on a shared machine a row still moves by ~10% between runs.
+
+// lfu.rs is also a standalone program; its main() and demo-only items are unused here
+#[allow(dead_code)]
+#[path = "lfu.rs"]
+pub mod lfu;
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::process;
+use std::time::Instant;
+
+use lfu::LFUCache;
+
+const USAGE: &str = "usage: lfu_bench [ops per run]";
+const ROUNDS: usize = 7;
+
+struct HashListNode {
+    key: u64,
+    val: u64,
+    freq: usize,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+// Frequency lists in HashMaps, get/put only
+struct HashListLFU {
+    capacity: usize,
+    min_freq: usize,
+    key_map: HashMap<u64, usize>,
+    freq_heads: HashMap<usize, usize>,
+    freq_tails: HashMap<usize, usize>,
+    nodes: Vec<HashListNode>,
+}
+
+impl HashListLFU {
+    fn new(capacity: usize) -> Self {
+        HashListLFU {
+            capacity,
+            min_freq: 0,
+            key_map: HashMap::new(),
+            freq_heads: HashMap::new(),
+            freq_tails: HashMap::new(),
+            nodes: Vec::with_capacity(capacity),
+        }
+    }
+
+    fn get(&mut self, key: &u64) -> Option<&u64> {
+        let node_idx = *self.key_map.get(key)?;
+        self.remove_from_freq_list(node_idx);
+        self.nodes[node_idx].freq += 1;
+        self.add_to_freq_list(node_idx);
+        Some(&self.nodes[node_idx].val)
+    }
+
+    // Hands back what it evicts, like LFUCache::put, so both pay for the Vec
+    fn put(&mut self, key: u64, val: u64) -> Vec<(u64, u64)> {
+        if self.capacity == 0 {
+            return vec![(key, val)];
+        }
+        if let Some(&node_idx) = self.key_map.get(&key) {
+            self.remove_from_freq_list(node_idx);
+            self.nodes[node_idx].val = val;
+            self.nodes[node_idx].freq += 1;
+            self.add_to_freq_list(node_idx);
+            return Vec::new();
+        }
+        let node = HashListNode { key, val, freq: 1, prev: None, next: None };
+        let mut evicted = Vec::new();
+        let node_idx = if self.nodes.len() < self.capacity {
+            self.nodes.push(node);
+            self.nodes.len() - 1
+        } else {
+            // min_freq is always exact here, see remove_from_freq_list
+            let victim_idx = self.freq_tails[&self.min_freq];
+            self.remove_from_freq_list(victim_idx);
+            self.key_map.remove(&self.nodes[victim_idx].key);
+            let victim = std::mem::replace(&mut self.nodes[victim_idx], node);
+            evicted.push((victim.key, victim.val));
+            victim_idx
+        };
+        self.key_map.insert(key, node_idx);
+        self.add_to_freq_list(node_idx);
+        self.min_freq = 1;
+        evicted
+    }
+
+    fn remove_from_freq_list(&mut self, node_idx: usize) {
+        let freq = self.nodes[node_idx].freq;
+        let prev_opt = self.nodes[node_idx].prev;
+        let next_opt = self.nodes[node_idx].next;
+        match prev_opt {
+            Some(prev_idx) => self.nodes[prev_idx].next = next_opt,
+            None => match next_opt {
+                Some(next_idx) => {
+                    self.freq_heads.insert(freq, next_idx);
+                }
+                None => {
+                    self.freq_heads.remove(&freq);
+                }
+            },
+        }
+        match next_opt {
+            Some(next_idx) => self.nodes[next_idx].prev = prev_opt,
+            None => match prev_opt {
+                Some(prev_idx) => {
+                    self.freq_tails.insert(freq, prev_idx);
+                }
+                None => {
+                    self.freq_tails.remove(&freq);
+                }
+            },
+        }
+        // O(1) min_freq upkeep: put() resets it to 1 after every insert, and the
+        // only other way a list empties is a bump to freq + 1, which is then the minimum.
+        // (An evicted min_freq node is always followed by a Freq 1 insert.)
+        if freq == self.min_freq && prev_opt.is_none() && next_opt.is_none() {
+            self.min_freq += 1;
+        }
+    }
+
+    fn add_to_freq_list(&mut self, node_idx: usize) {
+        let freq = self.nodes[node_idx].freq;
+        self.nodes[node_idx].prev = None;
+        match self.freq_heads.insert(freq, node_idx) {
+            Some(old_head_idx) => {
+                self.nodes[node_idx].next = Some(old_head_idx);
+                self.nodes[old_head_idx].prev = Some(node_idx);
+            }
+            None => {
+                self.nodes[node_idx].next = None;
+                self.freq_tails.insert(freq, node_idx);
+            }
+        }
+    }
+}
+
+struct Workload {
+    name: &'static str,
+    capacity: usize,
+    keys: Vec<u64>,
+}
+
+// xorshift64*: fixed seed, so every layout sees the same key sequence
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // Uniform in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// Key k drawn with probability proportional to 1 / (k + 1)^exponent
+fn zipf_keys(rng: &mut Rng, distinct: usize, exponent: f64, ops: usize) -> Vec<u64> {
+    let mut cdf = Vec::with_capacity(distinct);
+    let mut total = 0.0;
+    for k in 0..distinct {
+        total += 1.0 / ((k + 1) as f64).powf(exponent);
+        cdf.push(total);
+    }
+    (0..ops)
+        .map(|_| {
+            let target = rng.next_f64() * total;
+            cdf.partition_point(|&c| c < target).min(distinct - 1) as u64
+        })
+        .collect()
+}
+
+fn workloads(ops: usize) -> Vec<Workload> {
+    let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+    vec![
+        Workload {
+            name: "zipf 0.9",
+            capacity: 10_000,
+            keys: zipf_keys(&mut rng, 1_000_000, 0.9, ops),
+        },
+        Workload {
+            name: "zipf 1.2",
+            capacity: 1_000,
+            keys: zipf_keys(&mut rng, 100_000, 1.2, ops),
+        },
+        Workload {
+            name: "uniform",
+            capacity: 10_000,
+            keys: (0..ops).map(|_| rng.next_u64() % 50_000).collect(),
+        },
+        // Every access hits, so the run is all frequency moves and no evictions
+        Workload {
+            name: "all hits",
+            capacity: 1_000,
+            keys: (0..ops).map(|_| rng.next_u64() % 1_000).collect(),
+        },
+    ]
+}
+
+// One run, in nanoseconds per access
+fn time_per_op<F: FnMut() -> u64>(ops: usize, mut run: F) -> f64 {
+    let start = Instant::now();
+    black_box(run());
+    start.elapsed().as_nanos() as f64 / ops.max(1) as f64
+}
+
+// Best of ROUNDS for each layout. Rounds alternate between the two, so a burst
+// of load on the machine hits both sides instead of skewing one.
+fn time_both(workload: &Workload, ops: usize) -> (f64, f64) {
+    let (mut hash_lists, mut buckets) = (f64::MAX, f64::MAX);
+    for _ in 0..ROUNDS {
+        hash_lists = hash_lists.min(time_per_op(ops, || run_hash_lists(workload.capacity, &workload.keys)));
+        buckets = buckets.min(time_per_op(ops, || run_buckets(workload.capacity, &workload.keys)));
+    }
+    (hash_lists, buckets)
+}
+
+fn run_buckets(capacity: usize, keys: &[u64]) -> u64 {
+    let mut cache = LFUCache::new(capacity);
+    let mut hits = 0;
+    for &key in keys {
+        if cache.get(&key).is_some() {
+            hits += 1;
+        } else {
+            cache.put(key, key);
+        }
+    }
+    hits
+}
+
+fn run_hash_lists(capacity: usize, keys: &[u64]) -> u64 {
+    let mut cache = HashListLFU::new(capacity);
+    let mut hits = 0;
+    for &key in keys {
+        if cache.get(&key).is_some() {
+            hits += 1;
+        } else {
+            cache.put(key, key);
+        }
+    }
+    hits
+}
+
+fn main() {
+    let ops = match std::env::args().nth(1).map(|arg| arg.parse::<usize>()) {
+        None => 1_000_000,
+        Some(Ok(ops)) if ops > 0 => ops,
+        Some(_) => {
+            eprintln!("Error: ops per run must be a positive number\n{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    println!("{} accesses per run, best of {}", ops, ROUNDS);
+    println!(
+        "{:<10} {:>9} {:>8} {:>14} {:>14} {:>9}",
+        "workload", "capacity", "hit %", "hash lists ns", "buckets ns", "speedup"
+    );
+    for workload in workloads(ops) {
+        let hits = run_buckets(workload.capacity, &workload.keys);
+        // Same eviction order, so both layouts must agree on every hit
+        assert_eq!(hits, run_hash_lists(workload.capacity, &workload.keys));
+
+        let (hash_lists, buckets) = time_both(&workload, ops);
+        println!(
+            "{:<10} {:>9} {:>7.2}% {:>14.1} {:>14.1} {:>8.2}x",
+            workload.name,
+            workload.capacity,
+            100.0 * hits as f64 / ops as f64,
+            hash_lists,
+            buckets,
+            hash_lists / buckets
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The baseline only means something if it evicts exactly like LFUCache
+    #[test]
+    fn test_hash_lists_match_lfu_cache() {
+        let mut rng = Rng(7);
+        let keys = zipf_keys(&mut rng, 500, 1.0, 20_000);
+        for &capacity in &[0, 1, 7, 50, 499] {
+            let mut buckets = LFUCache::new(capacity);
+            let mut hash_lists = HashListLFU::new(capacity);
+            for &key in &keys {
+                let expected = buckets.get(&key).copied();
+                assert_eq!(hash_lists.get(&key).copied(), expected);
+                if expected.is_none() {
+                    assert_eq!(hash_lists.put(key, key), buckets.put(key, key));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_zipf_keys_skewed_and_in_range() {
+        let mut rng = Rng(1);
+        let keys = zipf_keys(&mut rng, 100, 1.2, 10_000);
+        assert!(keys.iter().all(|&k| k < 100));
+        let zeros = keys.iter().filter(|&&k| k == 0).count();
+        let nineties = keys.iter().filter(|&&k| k == 90).count();
+        assert!(zeros > 20 * nineties.max(1));
+    }
+}
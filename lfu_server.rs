@@ -0,0 +1,510 @@
+// Cache server speaking a subset of the memcached text protocol, backed by LFUCache.
+//
+// Listens on localhost only, so every process on the box can share one cache:
+//
+//   rustc -O lfu_server.rs && ./lfu_server --port 11211 --max-bytes 67108864
+//   printf 'set greeting 0 0 5\r\nhello\r\nget greeting\r\n' | nc localhost 11211
+//
+// Commands (https://github.com/memcached/memcached/blob/master/doc/protocol.txt):
+//   get <key>*                                       VALUE <key> <flags> <bytes> ... END
+//   set <key> <flags> <exptime> <bytes> [noreply]    STORED
+//   delete <key> [noreply]                           DELETED | NOT_FOUND
+//   stats                                            STAT <name> <value> ... END
+//   quit
+//
+// The cache is weighted by key + value bytes, up to --max-bytes. One thread per
+// connection; all of them share the cache behind a Mutex.
+
+// lfu.rs is also a standalone program; its main() and demo-only items are unused here
+#[allow(dead_code)]
+#[path = "lfu.rs"]
+pub mod lfu;
+
+use std::cell::Cell;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use lfu::{EvictionCause, LFUCache};
+
+const USAGE: &str = "usage: lfu_server [--port N] [--max-bytes N]";
+
+// Limits from memcached's defaults
+const MAX_KEY_LEN: usize = 250;
+const MAX_LINE_LEN: u64 = 2048;
+const MAX_VALUE_LEN: usize = 1 << 20;
+// exptime above this many seconds is an absolute unix time, not a relative one
+const RELATIVE_EXPTIME_LIMIT: i64 = 60 * 60 * 24 * 30;
+
+struct Options {
+    port: u16,
+    max_bytes: usize,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        port: 11211,
+        max_bytes: 64 << 20,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--port" => options.port = value?.parse().map_err(|_| "bad port".to_string())?,
+            "--max-bytes" => options.max_bytes = value?.parse().map_err(|_| "bad byte limit".to_string())?,
+            other => return Err(format!("unknown option {}", other)),
+        }
+    }
+    Ok(options)
+}
+
+#[derive(Clone)]
+struct Item {
+    flags: u32,
+    data: Vec<u8>,
+    // Set by get; the cache only hands out shared references, and its Mutex
+    // already serializes every access
+    fetched: Cell<bool>,
+}
+
+// Everything the connection threads share
+struct Server {
+    cache: Mutex<LFUCache<Vec<u8>, Item>>,
+    max_bytes: usize,
+    started: Instant,
+    curr_connections: AtomicU64,
+    total_connections: AtomicU64,
+    cmd_get: AtomicU64,
+    cmd_set: AtomicU64,
+    delete_hits: AtomicU64,
+    delete_misses: AtomicU64,
+    // Items that expired before any get returned them; bumped by the eviction listener
+    expired_unfetched: Arc<AtomicU64>,
+}
+
+impl Server {
+    fn new(max_bytes: usize) -> Self {
+        let expired_unfetched = Arc::new(AtomicU64::new(0));
+        let counter = Arc::clone(&expired_unfetched);
+        let cache = LFUCache::weighted(max_bytes)
+            .with_weigher(|key: &Vec<u8>, item: &Item| key.len() + item.data.len())
+            .with_eviction_listener(move |_: &Vec<u8>, item: &Item, cause| {
+                if cause == EvictionCause::Expired && !item.fetched.get() {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        Server {
+            cache: Mutex::new(cache),
+            max_bytes,
+            started: Instant::now(),
+            curr_connections: AtomicU64::new(0),
+            total_connections: AtomicU64::new(0),
+            cmd_get: AtomicU64::new(0),
+            cmd_set: AtomicU64::new(0),
+            delete_hits: AtomicU64::new(0),
+            delete_misses: AtomicU64::new(0),
+            expired_unfetched,
+        }
+    }
+
+    fn cache(&self) -> std::sync::MutexGuard<'_, LFUCache<Vec<u8>, Item>> {
+        // A panicking connection thread must not take the whole cache down with it
+        self.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// Accept connections forever, one thread each
+fn serve(listener: TcpListener, server: Arc<Server>) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("accept failed: {}", e);
+                continue;
+            }
+        };
+        let server = Arc::clone(&server);
+        thread::spawn(move || {
+            server.curr_connections.fetch_add(1, Ordering::Relaxed);
+            server.total_connections.fetch_add(1, Ordering::Relaxed);
+            if let Err(e) = handle_connection(&stream, &server) {
+                eprintln!("connection error: {}", e);
+            }
+            server.curr_connections.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+}
+
+fn handle_connection(stream: &TcpStream, server: &Server) -> io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut writer = io::BufWriter::new(stream);
+    loop {
+        let mut line = Vec::new();
+        (&mut reader).take(MAX_LINE_LEN).read_until(b'\n', &mut line)?;
+        if line.is_empty() {
+            return Ok(()); // client hung up
+        }
+        if !line.ends_with(b"\n") {
+            writer.write_all(b"CLIENT_ERROR line too long\r\n")?;
+            return writer.flush();
+        }
+        match handle_command(&line, &mut reader, server)? {
+            Reply::Send(reply) => {
+                writer.write_all(&reply)?;
+                writer.flush()?;
+            }
+            Reply::Silent => {}
+            Reply::Close => return writer.flush(),
+        }
+    }
+}
+
+enum Reply {
+    Send(Vec<u8>),
+    // noreply was given
+    Silent,
+    Close,
+}
+
+// Run one command line. `data` supplies set's data block.
+fn handle_command<R: BufRead>(line: &[u8], data: &mut R, server: &Server) -> io::Result<Reply> {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let words: Vec<&[u8]> = line.split(|&b| b == b' ').filter(|word| !word.is_empty()).collect();
+    let reply = match words.split_first() {
+        Some((&b"get", keys)) if !keys.is_empty() => cmd_get(keys, server),
+        Some((&b"set", args)) => return cmd_set(args, data, server),
+        Some((&b"delete", args)) => cmd_delete(args, server),
+        Some((&b"stats", [])) => cmd_stats(server),
+        Some((&b"quit", [])) => return Ok(Reply::Close),
+        Some((&b"get", _)) | Some((&b"stats", _)) | Some((&b"quit", _)) => {
+            Reply::Send(b"CLIENT_ERROR bad command line format\r\n".to_vec())
+        }
+        _ => Reply::Send(b"ERROR\r\n".to_vec()),
+    };
+    Ok(reply)
+}
+
+fn valid_key(key: &[u8]) -> bool {
+    key.len() <= MAX_KEY_LEN && key.iter().all(|&b| b > b' ' && b != 0x7f)
+}
+
+fn parse<T: std::str::FromStr>(word: &[u8]) -> Option<T> {
+    std::str::from_utf8(word).ok()?.parse().ok()
+}
+
+fn cmd_get(keys: &[&[u8]], server: &Server) -> Reply {
+    let mut out = Vec::new();
+    let mut cache = server.cache();
+    for &key in keys {
+        server.cmd_get.fetch_add(1, Ordering::Relaxed);
+        if let Some(item) = cache.get(key) {
+            item.fetched.set(true);
+            out.extend_from_slice(b"VALUE ");
+            out.extend_from_slice(key);
+            out.extend_from_slice(format!(" {} {}\r\n", item.flags, item.data.len()).as_bytes());
+            out.extend_from_slice(&item.data);
+            out.extend_from_slice(b"\r\n");
+        }
+    }
+    out.extend_from_slice(b"END\r\n");
+    Reply::Send(out)
+}
+
+// set <key> <flags> <exptime> <bytes> [noreply], then <bytes> of data and \r\n
+fn cmd_set<R: BufRead>(args: &[&[u8]], data: &mut R, server: &Server) -> io::Result<Reply> {
+    let parsed = match args {
+        [key, flags, exptime, bytes] | [key, flags, exptime, bytes, b"noreply"] => {
+            match (parse::<u32>(flags), parse::<i64>(exptime), parse::<usize>(bytes)) {
+                (Some(flags), Some(exptime), Some(bytes)) => Some((*key, flags, exptime, bytes)),
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+    let (key, flags, exptime, len) = match parsed {
+        Some(parsed) if valid_key(parsed.0) => parsed,
+        _ => return Ok(Reply::Send(b"CLIENT_ERROR bad command line format\r\n".to_vec())),
+    };
+    let noreply = args.len() == 5;
+    let reply = |text: &[u8]| if noreply { Reply::Silent } else { Reply::Send(text.to_vec()) };
+
+    if len > MAX_VALUE_LEN {
+        // Swallow the data block so the next line parses as a command
+        io::copy(&mut data.take(len as u64 + 2), &mut io::sink())?;
+        return Ok(reply(b"SERVER_ERROR object too large for cache\r\n"));
+    }
+    let mut block = vec![0; len + 2];
+    data.read_exact(&mut block)?;
+    if !block.ends_with(b"\r\n") {
+        return Ok(reply(b"CLIENT_ERROR bad data chunk\r\n"));
+    }
+    block.truncate(len);
+
+    server.cmd_set.fetch_add(1, Ordering::Relaxed);
+    let item = Item {
+        flags,
+        data: block,
+        fetched: Cell::new(false),
+    };
+    let mut cache = server.cache();
+    let handed_back = match ttl_from_exptime(exptime, SystemTime::now()) {
+        // Already expired: memcached stores it and it is never seen again
+        Some(ttl) if ttl == Duration::ZERO => {
+            cache.remove(key);
+            return Ok(reply(b"STORED\r\n"));
+        }
+        Some(ttl) => cache.put_with_ttl(key.to_vec(), item, ttl),
+        None => cache.put(key.to_vec(), item),
+    };
+    // put() hands the pair back instead of evicting it when it can never fit
+    if handed_back.iter().any(|(evicted, _)| *evicted == key) {
+        return Ok(reply(b"SERVER_ERROR out of memory storing object\r\n"));
+    }
+    Ok(reply(b"STORED\r\n"))
+}
+
+// memcached exptime: 0 = never, up to 30 days = seconds from now, above that = unix time,
+// negative = already expired (Duration::ZERO)
+fn ttl_from_exptime(exptime: i64, now: SystemTime) -> Option<Duration> {
+    if exptime == 0 {
+        return None;
+    }
+    if exptime < 0 {
+        return Some(Duration::ZERO);
+    }
+    if exptime <= RELATIVE_EXPTIME_LIMIT {
+        return Some(Duration::from_secs(exptime as u64));
+    }
+    let deadline = UNIX_EPOCH + Duration::from_secs(exptime as u64);
+    Some(deadline.duration_since(now).unwrap_or(Duration::ZERO))
+}
+
+fn cmd_delete(args: &[&[u8]], server: &Server) -> Reply {
+    let (key, noreply) = match args {
+        [key] => (*key, false),
+        [key, b"noreply"] => (*key, true),
+        _ => return Reply::Send(b"CLIENT_ERROR bad command line format\r\n".to_vec()),
+    };
+    let deleted = server.cache().remove(key).is_some();
+    let text: &[u8] = if deleted {
+        server.delete_hits.fetch_add(1, Ordering::Relaxed);
+        b"DELETED\r\n"
+    } else {
+        server.delete_misses.fetch_add(1, Ordering::Relaxed);
+        b"NOT_FOUND\r\n"
+    };
+    if noreply {
+        Reply::Silent
+    } else {
+        Reply::Send(text.to_vec())
+    }
+}
+
+fn cmd_stats(server: &Server) -> Reply {
+    let cache_stats = server.cache().stats();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let counter = |c: &AtomicU64| c.load(Ordering::Relaxed);
+    let stats: Vec<(&str, u64)> = vec![
+        ("pid", u64::from(process::id())),
+        ("uptime", server.started.elapsed().as_secs()),
+        ("time", now),
+        ("curr_connections", counter(&server.curr_connections)),
+        ("total_connections", counter(&server.total_connections)),
+        ("cmd_get", counter(&server.cmd_get)),
+        ("cmd_set", counter(&server.cmd_set)),
+        ("get_hits", cache_stats.hits),
+        ("get_misses", cache_stats.misses),
+        ("delete_hits", counter(&server.delete_hits)),
+        ("delete_misses", counter(&server.delete_misses)),
+        ("curr_items", cache_stats.len as u64),
+        ("bytes", cache_stats.total_weight as u64),
+        ("limit_maxbytes", server.max_bytes as u64),
+        ("evictions", cache_stats.evictions),
+        ("expired_unfetched", counter(&server.expired_unfetched)),
+    ];
+    let mut out = Vec::new();
+    for (name, value) in stats {
+        out.extend_from_slice(format!("STAT {} {}\r\n", name, value).as_bytes());
+    }
+    out.extend_from_slice(b"END\r\n");
+    Reply::Send(out)
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Error: {}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    let listener = match TcpListener::bind((Ipv4Addr::LOCALHOST, options.port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Error: cannot listen on localhost:{}: {}", options.port, e);
+            process::exit(1);
+        }
+    };
+    println!("lfu_server listening on localhost:{}, {} bytes max", options.port, options.max_bytes);
+    serve(listener, Arc::new(Server::new(options.max_bytes)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Start a server on a free loopback port and connect to it
+    fn connect(max_bytes: usize) -> (BufReader<TcpStream>, TcpStream) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Arc::new(Server::new(max_bytes));
+        thread::spawn(move || serve(listener, server));
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        (BufReader::new(stream.try_clone().unwrap()), stream)
+    }
+
+    fn send(stream: &mut TcpStream, request: &str) {
+        stream.write_all(request.as_bytes()).unwrap();
+    }
+
+    fn read_line(reader: &mut BufReader<TcpStream>) -> String {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        line
+    }
+
+    // Lines up to and including END
+    fn read_until_end(reader: &mut BufReader<TcpStream>) -> Vec<String> {
+        let mut lines = Vec::new();
+        loop {
+            let line = read_line(reader);
+            assert!(!line.is_empty(), "connection closed before END");
+            let done = line == "END\r\n";
+            lines.push(line);
+            if done {
+                return lines;
+            }
+        }
+    }
+
+    #[test]
+    fn test_set_get_delete_over_loopback() {
+        let (mut reader, mut stream) = connect(1 << 20);
+        send(&mut stream, "set greeting 42 0 5\r\nhello\r\n");
+        assert_eq!(read_line(&mut reader), "STORED\r\n");
+        send(&mut stream, "get greeting missing\r\n");
+        assert_eq!(read_until_end(&mut reader), vec!["VALUE greeting 42 5\r\n", "hello\r\n", "END\r\n"]);
+
+        // Data may contain spaces and line breaks; only the byte count matters
+        send(&mut stream, "set multi 0 0 7\r\na b\r\ncd\r\nget multi\r\n");
+        assert_eq!(read_line(&mut reader), "STORED\r\n");
+        assert_eq!(read_until_end(&mut reader), vec!["VALUE multi 0 7\r\n", "a b\r\n", "cd\r\n", "END\r\n"]);
+
+        send(&mut stream, "delete greeting\r\ndelete greeting\r\nget greeting\r\n");
+        assert_eq!(read_line(&mut reader), "DELETED\r\n");
+        assert_eq!(read_line(&mut reader), "NOT_FOUND\r\n");
+        assert_eq!(read_until_end(&mut reader), vec!["END\r\n"]);
+    }
+
+    #[test]
+    fn test_noreply_and_errors() {
+        let (mut reader, mut stream) = connect(1 << 20);
+        send(&mut stream, "set k 0 0 1 noreply\r\nx\r\ndelete nothing noreply\r\n");
+        send(&mut stream, "bogus\r\nset k 0 0\r\nset k 0 0 2\r\nabc\r\nget\r\n");
+        assert_eq!(read_line(&mut reader), "ERROR\r\n");
+        assert_eq!(read_line(&mut reader), "CLIENT_ERROR bad command line format\r\n");
+        // 2 bytes promised: the block read is "abc\r", and the "\n" left over is an empty command
+        assert_eq!(read_line(&mut reader), "CLIENT_ERROR bad data chunk\r\n");
+        assert_eq!(read_line(&mut reader), "ERROR\r\n");
+        assert_eq!(read_line(&mut reader), "CLIENT_ERROR bad command line format\r\n");
+        send(&mut stream, "get k\r\n");
+        assert_eq!(read_until_end(&mut reader), vec!["VALUE k 0 1\r\n", "x\r\n", "END\r\n"]);
+    }
+
+    #[test]
+    fn test_eviction_and_stats() {
+        // Room for two 1-byte keys with 4-byte values
+        let (mut reader, mut stream) = connect(10);
+        send(&mut stream, "set a 0 0 4\r\naaaa\r\nset b 0 0 4\r\nbbbb\r\nget a\r\n");
+        assert_eq!(read_line(&mut reader), "STORED\r\n");
+        assert_eq!(read_line(&mut reader), "STORED\r\n");
+        read_until_end(&mut reader);
+        send(&mut stream, "set c 0 0 4\r\ncccc\r\nget b\r\nset huge 0 0 20\r\n01234567890123456789\r\n");
+        assert_eq!(read_line(&mut reader), "STORED\r\n");
+        assert_eq!(read_until_end(&mut reader), vec!["END\r\n"]); // b was the LFU entry
+        assert_eq!(read_line(&mut reader), "SERVER_ERROR out of memory storing object\r\n");
+
+        send(&mut stream, "stats\r\n");
+        let stats = read_until_end(&mut reader);
+        let stat = |name: &str| {
+            let prefix = format!("STAT {} ", name);
+            stats.iter().find_map(|line| line.strip_prefix(prefix.as_str())).map(|v| v.trim().to_string())
+        };
+        assert_eq!(stat("curr_items"), Some("2".to_string()));
+        assert_eq!(stat("bytes"), Some("10".to_string()));
+        assert_eq!(stat("evictions"), Some("1".to_string()));
+        assert_eq!(stat("get_hits"), Some("1".to_string()));
+        assert_eq!(stat("get_misses"), Some("1".to_string()));
+        assert_eq!(stat("cmd_set"), Some("4".to_string()));
+        assert_eq!(stat("curr_connections"), Some("1".to_string()));
+    }
+
+    #[test]
+    fn test_expired_unfetched_skips_items_that_were_read() {
+        let server = Server::new(1 << 20);
+        let item = |data: &[u8]| Item {
+            flags: 0,
+            data: data.to_vec(),
+            fetched: Cell::new(false),
+        };
+        {
+            let mut cache = server.cache();
+            cache.put_with_ttl(b"read".to_vec(), item(b"r"), Duration::from_millis(20));
+            cache.put_with_ttl(b"unread".to_vec(), item(b"u"), Duration::from_millis(20));
+        }
+        cmd_get(&[b"read"], &server);
+        thread::sleep(Duration::from_millis(50));
+        cmd_get(&[b"read", b"unread"], &server);
+
+        assert_eq!(server.cache().stats().expirations, 2);
+        assert_eq!(server.expired_unfetched.load(Ordering::Relaxed), 1);
+        let reply = match cmd_stats(&server) {
+            Reply::Send(reply) => String::from_utf8(reply).unwrap(),
+            _ => panic!("stats should reply"),
+        };
+        assert!(reply.contains("STAT expired_unfetched 1\r\n"));
+    }
+
+    #[test]
+    fn test_quit_closes_connection() {
+        let (mut reader, mut stream) = connect(1 << 20);
+        send(&mut stream, "quit\r\n");
+        assert_eq!(read_line(&mut reader), "");
+    }
+
+    #[test]
+    fn test_ttl_from_exptime() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        assert_eq!(ttl_from_exptime(0, now), None);
+        assert_eq!(ttl_from_exptime(-1, now), Some(Duration::ZERO));
+        assert_eq!(ttl_from_exptime(60, now), Some(Duration::from_secs(60)));
+        assert_eq!(ttl_from_exptime(1_000_000_100, now), Some(Duration::from_secs(100)));
+        assert_eq!(ttl_from_exptime(999_999_999, now), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_negative_exptime_deletes() {
+        let (mut reader, mut stream) = connect(1 << 20);
+        send(&mut stream, "set k 0 0 1\r\nx\r\nset k 0 -1 1\r\ny\r\nget k\r\n");
+        assert_eq!(read_line(&mut reader), "STORED\r\n");
+        assert_eq!(read_line(&mut reader), "STORED\r\n");
+        assert_eq!(read_until_end(&mut reader), vec!["END\r\n"]);
+    }
+}
@@ -0,0 +1,288 @@
+// Index arena + doubly linked lists, shared by LFUCache (one list per
+// frequency bucket) and the LRU, 2Q and ARC policies in cache_policy.
+//
+//   slots:  [0]        [1]        [2]        [3]
+//           key=A      key=B      key=C      (free)
+//           list=0     list=0     list=1
+//           prev=None  prev=0     prev=None
+//           next=1     next=None  next=None
+//
+//   lists[0]: head=0 (MRU) -> 1 (LRU)=tail      lists[1]: head=tail=2
+//
+// Lists are numbered. new() makes a fixed set of them; add_list() and
+// free_list() open and close more while running, reusing closed numbers.
+//
+// Entries store their list and neighbours as u32 (the links as index + 1, so
+// None costs nothing): 12 bytes of bookkeeping per item instead of 40, which
+// keeps more of a large cache in the CPU cache. The slab therefore holds at
+// most u32::MAX - 1 entries and lists.
+
+use std::num::NonZeroU32;
+
+// Slot index + 1; Option<Link> is 4 bytes
+type Link = NonZeroU32;
+
+fn link(idx: usize) -> Link {
+    <u32 as std::convert::TryFrom<usize>>::try_from(idx + 1)
+        .ok()
+        .and_then(NonZeroU32::new)
+        .expect("LinkedSlab holds at most u32::MAX - 1 entries")
+}
+
+fn index(link: Option<Link>) -> Option<usize> {
+    link.map(|link| link.get() as usize - 1)
+}
+
+struct SlabEntry<T> {
+    item: T,
+    list: u32,
+    prev: Option<Link>,
+    next: Option<Link>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct ListEnds {
+    head: Option<usize>, // MRU
+    tail: Option<usize>, // LRU
+    len: usize,
+}
+
+/// Arena of items, each threaded onto one of its doubly linked lists.
+/// Indices stay valid until remove(); freed slots are reused before the arena grows.
+pub struct LinkedSlab<T> {
+    entries: Vec<Option<SlabEntry<T>>>,
+    free: Vec<usize>,
+    lists: Vec<ListEnds>,
+    free_lists: Vec<usize>,
+}
+
+impl<T> LinkedSlab<T> {
+    pub fn new(list_count: usize) -> Self {
+        Self::with_capacity(list_count, 0)
+    }
+
+    /// new() with room for `capacity` entries before the arena reallocates.
+    pub fn with_capacity(list_count: usize, capacity: usize) -> Self {
+        LinkedSlab {
+            entries: Vec::with_capacity(capacity),
+            free: Vec::new(),
+            lists: vec![ListEnds::default(); list_count],
+            free_lists: Vec::new(),
+        }
+    }
+
+    fn entry(&self, idx: usize) -> &SlabEntry<T> {
+        self.entries[idx].as_ref().expect("index should point at a live entry")
+    }
+
+    fn entry_mut(&mut self, idx: usize) -> &mut SlabEntry<T> {
+        self.entries[idx].as_mut().expect("index should point at a live entry")
+    }
+
+    /// Open an empty list and return its number (a freed one if there is one).
+    pub fn add_list(&mut self) -> usize {
+        match self.free_lists.pop() {
+            Some(list) => list,
+            None => {
+                assert!(self.lists.len() < u32::MAX as usize, "LinkedSlab holds at most u32::MAX - 1 lists");
+                self.lists.push(ListEnds::default());
+                self.lists.len() - 1
+            }
+        }
+    }
+
+    /// Close an empty list; add_list() hands its number out again.
+    pub fn free_list(&mut self, list: usize) {
+        debug_assert_eq!(self.lists[list].len, 0, "only an empty list can be freed");
+        self.free_lists.push(list);
+    }
+
+    /// Close every list. Entries stay stored but sit on no list until
+    /// link_front() puts each one on a list from add_list().
+    pub fn clear_lists(&mut self) {
+        self.lists.clear();
+        self.free_lists.clear();
+    }
+
+    /// Drop every entry. The lists stay open, empty.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.free.clear();
+        for ends in &mut self.lists {
+            *ends = ListEnds::default();
+        }
+    }
+
+    /// Open lists, i.e. every number add_list() or new() gave out and free_list() did not take back.
+    pub fn list_count(&self) -> usize {
+        self.lists.len() - self.free_lists.len()
+    }
+
+    /// Store `item` at the head (MRU end) of `list` and return its index.
+    pub fn push_front(&mut self, list: usize, item: T) -> usize {
+        let entry = SlabEntry {
+            item,
+            list: list as u32,
+            prev: None,
+            next: None,
+        };
+        let idx = match self.free.pop() {
+            Some(idx) => {
+                self.entries[idx] = Some(entry);
+                idx
+            }
+            None => {
+                self.entries.push(Some(entry));
+                self.entries.len() - 1
+            }
+        };
+        self.link_front(list, idx);
+        idx
+    }
+
+    /// Put an entry that is on no list (just unlink()ed) at the head of `list`.
+    pub fn link_front(&mut self, list: usize, idx: usize) {
+        let old_head = self.lists[list].head;
+        {
+            let entry = self.entry_mut(idx);
+            entry.list = list as u32;
+            entry.prev = None;
+            entry.next = old_head.map(link);
+        }
+        match old_head {
+            Some(head_idx) => self.entry_mut(head_idx).prev = Some(link(idx)),
+            None => self.lists[list].tail = Some(idx),
+        }
+        self.lists[list].head = Some(idx);
+        self.lists[list].len += 1;
+    }
+
+    /// Take an entry off its list but keep it stored, for link_front() to place again.
+    /// Returns the list it was on.
+    pub fn unlink(&mut self, idx: usize) -> usize {
+        let (list, prev, next) = {
+            let entry = self.entry(idx);
+            (entry.list as usize, entry.prev, entry.next)
+        };
+        match index(prev) {
+            Some(prev_idx) => self.entry_mut(prev_idx).next = next,
+            None => self.lists[list].head = index(next),
+        }
+        match index(next) {
+            Some(next_idx) => self.entry_mut(next_idx).prev = prev,
+            None => self.lists[list].tail = index(prev),
+        }
+        self.lists[list].len -= 1;
+        list
+    }
+
+    /// Move an entry to the head of `list` (its own list or another one).
+    pub fn move_to_front(&mut self, list: usize, idx: usize) {
+        self.unlink(idx);
+        self.link_front(list, idx);
+    }
+
+    /// Unlink an entry, free its slot and return the item.
+    pub fn remove(&mut self, idx: usize) -> T {
+        self.unlink(idx);
+        let entry = self.entries[idx].take().expect("index should point at a live entry");
+        self.free.push(idx);
+        entry.item
+    }
+
+    /// Index of the MRU end of `list`.
+    pub fn front(&self, list: usize) -> Option<usize> {
+        self.lists[list].head
+    }
+
+    /// Index of the LRU end of `list`.
+    pub fn back(&self, list: usize) -> Option<usize> {
+        self.lists[list].tail
+    }
+
+    /// Neighbour one step toward the head (more recently used).
+    pub fn prev(&self, idx: usize) -> Option<usize> {
+        index(self.entry(idx).prev)
+    }
+
+    /// Neighbour one step toward the tail (less recently used).
+    pub fn next(&self, idx: usize) -> Option<usize> {
+        index(self.entry(idx).next)
+    }
+
+    pub fn list_len(&self, list: usize) -> usize {
+        self.lists[list].len
+    }
+
+    pub fn list_of(&self, idx: usize) -> usize {
+        self.entry(idx).list as usize
+    }
+
+    pub fn get(&self, idx: usize) -> &T {
+        &self.entry(idx).item
+    }
+
+    pub fn get_mut(&mut self, idx: usize) -> &mut T {
+        &mut self.entry_mut(idx).item
+    }
+
+    /// get() for an index that may be stale: None if it is out of range or freed.
+    pub fn try_get(&self, idx: usize) -> Option<&T> {
+        self.entries.get(idx)?.as_ref().map(|entry| &entry.item)
+    }
+
+    /// Every stored item with its index, in slot order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, slot)| slot.as_ref().map(|entry| (idx, &entry.item)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slab_lists_and_slot_reuse() {
+        let mut slab = LinkedSlab::new(2);
+        let a = slab.push_front(0, "a");
+        let b = slab.push_front(0, "b");
+        let c = slab.push_front(1, "c");
+        assert_eq!(slab.back(0), Some(a));
+        assert_eq!((slab.list_len(0), slab.list_len(1)), (2, 1));
+
+        slab.move_to_front(1, a);
+        assert_eq!(slab.back(0), Some(b));
+        assert_eq!(slab.back(1), Some(c));
+        assert_eq!(slab.list_of(a), 1);
+
+        assert_eq!(slab.remove(b), "b");
+        assert_eq!(slab.back(0), None);
+        assert_eq!(slab.push_front(0, "d"), b);
+    }
+
+    #[test]
+    fn test_added_lists_are_freed_and_reused() {
+        let mut slab = LinkedSlab::new(0);
+        let first = slab.add_list();
+        let second = slab.add_list();
+        let a = slab.push_front(first, "a");
+        let b = slab.push_front(second, "b");
+        assert_eq!(slab.list_count(), 2);
+
+        slab.unlink(a);
+        slab.free_list(first);
+        slab.link_front(second, a);
+        assert_eq!(slab.list_count(), 1);
+        assert_eq!((slab.front(second), slab.back(second)), (Some(a), Some(b)));
+        assert_eq!((slab.next(a), slab.prev(b)), (Some(b), Some(a)));
+        assert_eq!(slab.add_list(), first);
+
+        slab.remove(a);
+        assert_eq!(slab.try_get(a), None);
+        assert_eq!(slab.try_get(b), Some(&"b"));
+        assert_eq!(slab.iter().collect::<Vec<_>>(), vec![(b, &"b")]);
+    }
+}
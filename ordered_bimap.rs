@@ -0,0 +1,303 @@
+// Ordered bidirectional map with range queries in both directions.
+//
+// BiMap is built on HashMaps, so it can only answer exact-match lookups.
+// OrderedBiMap keeps the same two-map layout on BTreeMaps instead: pairs can
+// be walked in left order or in right order, and either side can be asked for
+// a range ("hosts whose IP is in 10.0.0.0-10.0.255.255", "names from a to m").
+//
+//   rustc ordered_bimap.rs && ./ordered_bimap
+
+// bidirectional_map.rs is also a standalone program; its main() is unused here
+#[allow(dead_code)]
+#[path = "bidirectional_map.rs"]
+pub mod bidirectional_map;
+
+use std::borrow::Borrow;
+use std::collections::btree_map;
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+use std::ops::{Bound, RangeBounds};
+
+use bidirectional_map::{put_pair, OnConflict, RightTaken};
+
+/// Bidirectional Map over sorted maps: the same 1-to-1 rules as BiMap (including
+/// OnConflict), plus ordered iteration and range queries from either side.
+/// Invariant: forward and reverse hold exactly the same pairs.
+/// Edge Case: Lookups are O(log n) instead of O(1) - the price of ordering.
+pub struct OrderedBiMap<L, R> {
+    forward: BTreeMap<L, R>,
+    reverse: BTreeMap<R, L>,
+    on_conflict: OnConflict,
+}
+
+impl<L: Ord + Clone, R: Ord + Clone> Default for OrderedBiMap<L, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<L: Ord + Clone, R: Ord + Clone> OrderedBiMap<L, R> {
+    pub fn new() -> Self {
+        OrderedBiMap {
+            forward: BTreeMap::new(),
+            reverse: BTreeMap::new(),
+            on_conflict: OnConflict::Overwrite,
+        }
+    }
+
+    /// Choose how put() handles a right value already bound elsewhere (default Overwrite).
+    pub fn with_conflict_policy(mut self, on_conflict: OnConflict) -> Self {
+        self.on_conflict = on_conflict;
+        self
+    }
+
+    /// Inserts a left-right pair; conflicts on `right` follow on_conflict, like BiMap::put.
+    pub fn put(&mut self, left: L, right: R) -> Result<(), RightTaken<L, R>> {
+        put_pair(&mut self.forward, &mut self.reverse, self.on_conflict, left, right)
+    }
+
+    pub fn get_by_left<Q>(&self, left: &Q) -> Option<&R>
+    where
+        L: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.forward.get(left)
+    }
+
+    pub fn get_by_right<Q>(&self, right: &Q) -> Option<&L>
+    where
+        R: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.reverse.get(right)
+    }
+
+    pub fn contains_left<Q>(&self, left: &Q) -> bool
+    where
+        L: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.forward.contains_key(left)
+    }
+
+    pub fn contains_right<Q>(&self, right: &Q) -> bool
+    where
+        R: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.reverse.contains_key(right)
+    }
+
+    pub fn remove_by_left<Q>(&mut self, left: &Q) -> Option<R>
+    where
+        L: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let right = self.forward.remove(left)?;
+        self.reverse.remove(&right);
+        Some(right)
+    }
+
+    pub fn remove_by_right<Q>(&mut self, right: &Q) -> Option<L>
+    where
+        R: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let left = self.reverse.remove(right)?;
+        self.forward.remove(&left);
+        Some(left)
+    }
+
+    pub fn len(&self) -> usize {
+        self.forward.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.forward.is_empty()
+    }
+
+    /// All pairs as (left, right), in ascending left order.
+    pub fn iter(&self) -> btree_map::Iter<'_, L, R> {
+        self.forward.iter()
+    }
+
+    /// All pairs as (right, left), in ascending right order.
+    pub fn iter_by_right(&self) -> btree_map::Iter<'_, R, L> {
+        self.reverse.iter()
+    }
+
+    /// Pairs whose left falls in `range`, as (left, right) in left order.
+    /// Edge Case: Inverted range (start > end) -> panics, like BTreeMap::range.
+    /// Edge Case: Borrowed unsized bounds (&str for String) only work as a
+    ///            (Bound, Bound) tuple; "a".."n" is not a RangeBounds<str>.
+    pub fn range_by_left<Q, B>(&self, range: B) -> btree_map::Range<'_, L, R>
+    where
+        L: Borrow<Q>,
+        Q: Ord + ?Sized,
+        B: RangeBounds<Q>,
+    {
+        self.forward.range(range)
+    }
+
+    /// Pairs whose right falls in `range`, as (right, left) in right order.
+    pub fn range_by_right<Q, B>(&self, range: B) -> btree_map::Range<'_, R, L>
+    where
+        R: Borrow<Q>,
+        Q: Ord + ?Sized,
+        B: RangeBounds<Q>,
+    {
+        self.reverse.range(range)
+    }
+}
+
+fn main() {
+    let mut hosts = OrderedBiMap::new();
+    let records = [
+        ("mail", Ipv4Addr::new(10, 0, 3, 25)),
+        ("api", Ipv4Addr::new(10, 0, 0, 12)),
+        ("zeta", Ipv4Addr::new(10, 1, 0, 1)),
+        ("backup", Ipv4Addr::new(192, 168, 1, 20)),
+        ("cache", Ipv4Addr::new(10, 0, 255, 255)),
+        ("nas", Ipv4Addr::new(192, 168, 1, 2)),
+    ];
+    for (name, ip) in records {
+        let _ = hosts.put(name.to_string(), ip);
+    }
+
+    println!("--- Test 1: Iterate in Name Order ---");
+    for (name, ip) in hosts.iter() {
+        println!("{:<8} {}", name, ip);
+    }
+
+    println!("\n--- Test 2: Iterate in IP Order ---");
+    for (ip, name) in hosts.iter_by_right() {
+        println!("{:<15} {}", ip, name);
+    }
+
+    println!("\n--- Test 3: Hosts in 10.0.0.0 - 10.0.255.255 ---");
+    // Edge Case: Both ends inclusive; 10.0.255.255 itself is in range, 10.1.0.1 is not
+    let subnet = Ipv4Addr::new(10, 0, 0, 0)..=Ipv4Addr::new(10, 0, 255, 255);
+    for (ip, name) in hosts.range_by_right(subnet) {
+        println!("{:<15} {}", ip, name);
+    }
+
+    println!("\n--- Test 4: Names from a to m ---");
+    // Edge Case: "a"..="m" stops at "m" itself and misses "mail"; up to "n" (excluded) covers every name starting a-m
+    for (name, ip) in hosts.range_by_left::<str, _>((Bound::Included("a"), Bound::Excluded("n"))) {
+        println!("{:<8} {}", name, ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bidirectional_map::tests::{assert_sides_in_sync, Rng};
+    use bidirectional_map::BiMap;
+
+    fn assert_in_sync<L: Ord + Clone + std::fmt::Debug, R: Ord + Clone>(map: &OrderedBiMap<L, R>) {
+        assert_sides_in_sync(map.forward.iter(), &map.reverse);
+    }
+
+    fn ip(a: u8, b: u8, c: u8, d: u8) -> Ipv4Addr {
+        Ipv4Addr::new(a, b, c, d)
+    }
+
+    #[test]
+    fn test_iterates_in_both_orders() {
+        let mut map = OrderedBiMap::new();
+        map.put("b", 3).unwrap();
+        map.put("c", 1).unwrap();
+        map.put("a", 2).unwrap();
+        let by_left: Vec<_> = map.iter().map(|(l, r)| (*l, *r)).collect();
+        assert_eq!(by_left, vec![("a", 2), ("b", 3), ("c", 1)]);
+        let by_right: Vec<_> = map.iter_by_right().map(|(r, l)| (*r, *l)).collect();
+        assert_eq!(by_right, vec![(1, "c"), (2, "a"), (3, "b")]);
+    }
+
+    #[test]
+    fn test_ip_range_by_right() {
+        let mut map = OrderedBiMap::new();
+        map.put("low", ip(9, 255, 255, 255)).unwrap();
+        map.put("first", ip(10, 0, 0, 0)).unwrap();
+        map.put("mid", ip(10, 0, 128, 7)).unwrap();
+        map.put("last", ip(10, 0, 255, 255)).unwrap();
+        map.put("high", ip(10, 1, 0, 0)).unwrap();
+        let found: Vec<_> = map
+            .range_by_right(ip(10, 0, 0, 0)..=ip(10, 0, 255, 255))
+            .map(|(_, name)| *name)
+            .collect();
+        assert_eq!(found, vec!["first", "mid", "last"]);
+        // Half-open range stops before its end
+        assert_eq!(map.range_by_right(ip(10, 0, 0, 0)..ip(10, 0, 255, 255)).count(), 2);
+    }
+
+    #[test]
+    fn test_name_range_by_left_with_borrowed_bounds() {
+        let mut map = OrderedBiMap::new();
+        for (n, name) in ["apple", "mango", "m", "nectar", "Zebra", "kiwi"].iter().enumerate() {
+            map.put(name.to_string(), n).unwrap();
+        }
+        let names: Vec<_> = map
+            .range_by_left::<str, _>((Bound::Included("a"), Bound::Excluded("n")))
+            .map(|(l, _)| l.as_str())
+            .collect();
+        assert_eq!(names, vec!["apple", "kiwi", "m", "mango"]);
+        // Uppercase sorts before lowercase
+        assert_eq!(map.range_by_left::<str, _>((Bound::Unbounded, Bound::Excluded("a"))).count(), 1);
+        assert_eq!(map.range_by_left::<str, _>((Bound::Included("n"), Bound::Unbounded)).count(), 1);
+        // Owned bounds work with plain range syntax
+        assert_eq!(map.range_by_left("kiwi".to_string()..="m".to_string()).count(), 2);
+    }
+
+    #[test]
+    fn test_ranges_skip_removed_and_overwritten_pairs() {
+        let mut map = OrderedBiMap::new();
+        for n in 0..10u32 {
+            map.put(n, n * 10).unwrap();
+        }
+        assert_eq!(map.remove_by_left(&3), Some(30));
+        assert_eq!(map.remove_by_right(&40), Some(4));
+        // Overwrite: 90 moves from 9 to 5, dropping 5's old value 50 and the pair (9, 90)
+        map.put(5, 90).unwrap();
+        let rights: Vec<_> = map.range_by_right(20..=90).map(|(r, _)| *r).collect();
+        assert_eq!(rights, vec![20, 60, 70, 80, 90]);
+        let lefts: Vec<_> = map.range_by_left(2..).map(|(l, _)| *l).collect();
+        assert_eq!(lefts, vec![2, 5, 6, 7, 8]);
+        assert!(!map.contains_left(&9));
+        assert!(!map.contains_right(&50));
+        assert_in_sync(&map);
+    }
+
+    // Property: under every policy, OrderedBiMap holds the same pairs as BiMap after
+    // any mix of puts and removals, and every range agrees with filtering them.
+    #[test]
+    fn test_property_matches_bimap_and_ranges_match_filter() {
+        for policy in [OnConflict::Overwrite, OnConflict::Reject, OnConflict::KeepExisting] {
+            for seed in 1..=100u64 {
+                let mut rng = Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+                let mut expected = BiMap::new().with_conflict_policy(policy);
+                let mut map = OrderedBiMap::new().with_conflict_policy(policy);
+                for _ in 0..200 {
+                    let (left, right) = (rng.below(20), rng.below(20));
+                    match rng.below(5) {
+                        0 => assert_eq!(map.remove_by_left(&left), expected.remove_by_left(&left)),
+                        1 => assert_eq!(map.remove_by_right(&right), expected.remove_by_right(&right)),
+                        _ => assert_eq!(map.put(left, right), expected.put(left, right), "{:?} seed {}", policy, seed),
+                    }
+                    assert_in_sync(&map);
+                    let mut pairs: Vec<_> = expected.iter().map(|(l, r)| (*l, *r)).collect();
+                    pairs.sort();
+                    assert_eq!(map.iter().map(|(l, r)| (*l, *r)).collect::<Vec<_>>(), pairs);
+
+                    let (lo, hi) = (rng.below(20), rng.below(20));
+                    let (lo, hi) = (lo.min(hi), lo.max(hi));
+                    let in_left: Vec<_> = pairs.iter().filter(|p| p.0 >= lo && p.0 < hi).copied().collect();
+                    assert_eq!(map.range_by_left(lo..hi).map(|(l, r)| (*l, *r)).collect::<Vec<_>>(), in_left);
+                    pairs.sort_by_key(|p| p.1);
+                    let in_right: Vec<_> = pairs.iter().filter(|p| p.1 >= lo && p.1 <= hi).map(|p| (p.1, p.0)).collect();
+                    assert_eq!(map.range_by_right(lo..=hi).map(|(r, l)| (*r, *l)).collect::<Vec<_>>(), in_right);
+                }
+            }
+        }
+    }
+}
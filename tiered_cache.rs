@@ -0,0 +1,687 @@
+// Two-tier cache: LFUCache holds the hot set in memory, DiskStore holds
+// everything in one append-only log file on local disk.
+//
+//   get:  memory hit  -> done
+//         memory miss -> disk -> found: promote into memory (may evict)
+//   put:  write-through  disk first, then memory. Evicted entries are already
+//                        on disk, so eviction costs nothing.
+//         write-back     memory only, key marked dirty. A dirty entry reaches
+//                        disk when LFUCache evicts it, or on flush().
+//
+// Log record layout (integers encoded as in LFUCache snapshots):
+//   body length u32 | !body length u32 | FNV-1a of body u32 | body
+//   body: op u8 (1 = put, 0 = delete) | key | value length | value bytes (put only)
+// The header tells a record cut short by a crash (it runs past the end of the
+// file) from a damaged one (bad length check, checksum or op byte).
+// The index maps each key to its latest value in the file. Overwritten values
+// and delete records are dead bytes until compact() rewrites the file.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::hash::Hash;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use super::{invalid_data, take_bytes, temp_path, LFUCache, SnapshotCodec};
+
+const OP_DELETE: u8 = 0;
+const OP_PUT: u8 = 1;
+const RECORD_HEADER: usize = 12;
+
+// Dead bytes tolerated before a put() compacts the log on its own
+const COMPACT_SLACK: u64 = 1 << 20;
+
+// Where a key's latest put record sits in the log
+#[derive(Debug, Clone, Copy)]
+struct Location {
+    value_at: u64,
+    value_len: usize,
+    record_len: u64,
+}
+
+// A replayed record: its key, its value's location (None for a delete) and its length
+type Replayed<K> = (K, Option<Location>, usize);
+
+// FNV-1a: cheap, and enough to tell a damaged record from a good one
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, &byte| (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193))
+}
+
+// Helper: Append `body` to `out` behind its record header
+fn write_record(out: &mut Vec<u8>, body: &[u8]) -> io::Result<()> {
+    let body_len = <u32 as std::convert::TryFrom<usize>>::try_from(body.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "log record over 4 GiB"))?;
+    body_len.encode(out);
+    (!body_len).encode(out);
+    checksum(body).encode(out);
+    out.extend_from_slice(body);
+    Ok(())
+}
+
+/// File-backed key-value store: an append-only log plus an in-memory index of
+/// each key's latest value. Keys live in memory, values only on disk.
+pub struct DiskStore<K, V> {
+    path: PathBuf,
+    file: File,
+    index: HashMap<K, Location>,
+    // Log length, and how much of it the index still points at
+    end: u64,
+    live_bytes: u64,
+    _value: PhantomData<V>,
+}
+
+impl<K: Hash + Eq + Clone + SnapshotCodec, V: SnapshotCodec> DiskStore<K, V> {
+    /// Open or create the log at `path` and rebuild the index from it.
+    /// A record cut short at the end of the file (a crash mid-write) is dropped from it.
+    /// A damaged record anywhere is an InvalidData error, and the file is left untouched.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        let mut store = DiskStore {
+            path,
+            file,
+            index: HashMap::new(),
+            end: 0,
+            live_bytes: 0,
+            _value: PhantomData,
+        };
+        let mut start = 0;
+        while start < bytes.len() {
+            let (key, location, record_len) = match Self::replay(&bytes, start)? {
+                Some(record) => record,
+                None => break,
+            };
+            match location {
+                Some(location) => store.track(key, location),
+                None => {
+                    store.untrack(&key);
+                }
+            }
+            start += record_len;
+            store.end = start as u64;
+        }
+        if store.end < bytes.len() as u64 {
+            store.file.set_len(store.end)?;
+        }
+        Ok(store)
+    }
+
+    // Helper: Decode the record that starts `start` bytes into `log`.
+    // Ok(None) if the file ends before the record does; InvalidData if it is damaged.
+    fn replay(log: &[u8], start: usize) -> io::Result<Option<Replayed<K>>> {
+        let corrupt = || invalid_data(&format!("damaged log record at byte {}", start));
+        let mut input = &log[start..];
+        if input.len() < RECORD_HEADER {
+            return Ok(None);
+        }
+        let body_len = u32::decode(&mut input)?;
+        let body_len_check = u32::decode(&mut input)?;
+        let body_checksum = u32::decode(&mut input)?;
+        if body_len_check != !body_len {
+            return Err(corrupt());
+        }
+        if input.len() < body_len as usize {
+            return Ok(None);
+        }
+        let body = &input[..body_len as usize];
+        if checksum(body) != body_checksum {
+            return Err(corrupt());
+        }
+
+        let mut fields = body;
+        let op = u8::decode(&mut fields).map_err(|_| corrupt())?;
+        let key = K::decode(&mut fields).map_err(|_| corrupt())?;
+        let location = match op {
+            OP_DELETE => None,
+            OP_PUT => {
+                let value_len = usize::decode(&mut fields).map_err(|_| corrupt())?;
+                let value_at = (start + RECORD_HEADER + body.len() - fields.len()) as u64;
+                take_bytes(&mut fields, value_len).map_err(|_| corrupt())?;
+                Some(Location {
+                    value_at,
+                    value_len,
+                    record_len: (RECORD_HEADER + body.len()) as u64,
+                })
+            }
+            _ => return Err(corrupt()),
+        };
+        if !fields.is_empty() {
+            return Err(corrupt());
+        }
+        Ok(Some((key, location, RECORD_HEADER + body.len())))
+    }
+
+    // Helper: Point the index at a new record for `key`
+    fn track(&mut self, key: K, location: Location) {
+        self.live_bytes += location.record_len;
+        if let Some(old) = self.index.insert(key, location) {
+            self.live_bytes -= old.record_len;
+        }
+    }
+
+    // Helper: Forget `key`. Returns whether it was stored.
+    fn untrack(&mut self, key: &K) -> bool {
+        match self.index.remove(key) {
+            Some(old) => {
+                self.live_bytes -= old.record_len;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Helper: Write a record at the end of the log
+    fn append(&mut self, record: &[u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(record)?;
+        self.end += record.len() as u64;
+        Ok(())
+    }
+
+    pub fn get(&mut self, key: &K) -> io::Result<Option<V>> {
+        let location = match self.index.get(key) {
+            Some(&location) => location,
+            None => return Ok(None),
+        };
+        let mut bytes = vec![0; location.value_len];
+        self.file.seek(SeekFrom::Start(location.value_at))?;
+        self.file.read_exact(&mut bytes)?;
+        V::decode(&mut &bytes[..]).map(Some)
+    }
+
+    pub fn put(&mut self, key: K, value: &V) -> io::Result<()> {
+        let mut value_bytes = Vec::new();
+        value.encode(&mut value_bytes);
+        let mut body = vec![OP_PUT];
+        key.encode(&mut body);
+        value_bytes.len().encode(&mut body);
+        let value_at = self.end + (RECORD_HEADER + body.len()) as u64;
+        body.extend_from_slice(&value_bytes);
+        let mut record = Vec::with_capacity(RECORD_HEADER + body.len());
+        write_record(&mut record, &body)?;
+
+        self.append(&record)?;
+        self.track(key, Location {
+            value_at,
+            value_len: value_bytes.len(),
+            record_len: record.len() as u64,
+        });
+        if self.end - self.live_bytes > self.live_bytes + COMPACT_SLACK {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Delete `key`. Returns whether it was stored.
+    pub fn remove(&mut self, key: &K) -> io::Result<bool> {
+        if !self.index.contains_key(key) {
+            return Ok(false);
+        }
+        let mut body = vec![OP_DELETE];
+        key.encode(&mut body);
+        let mut record = Vec::with_capacity(RECORD_HEADER + body.len());
+        write_record(&mut record, &body)?;
+        self.append(&record)?;
+        Ok(self.untrack(key))
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.index.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Size of the log file in bytes, dead records included.
+    pub fn file_len(&self) -> u64 {
+        self.end
+    }
+
+    /// Force everything written so far onto the disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+
+    /// Rewrite the log with only the live records. Written to a temp file, then renamed.
+    pub fn compact(&mut self) -> io::Result<()> {
+        let mut out = Vec::with_capacity(self.live_bytes as usize);
+        let mut index = HashMap::with_capacity(self.index.len());
+        for (key, &location) in &self.index {
+            let mut value_bytes = vec![0; location.value_len];
+            self.file.seek(SeekFrom::Start(location.value_at))?;
+            self.file.read_exact(&mut value_bytes)?;
+
+            let mut body = vec![OP_PUT];
+            key.encode(&mut body);
+            location.value_len.encode(&mut body);
+            let value_at = (out.len() + RECORD_HEADER + body.len()) as u64;
+            body.extend_from_slice(&value_bytes);
+            let start = out.len();
+            write_record(&mut out, &body)?;
+            index.insert(key.clone(), Location {
+                value_at,
+                value_len: location.value_len,
+                record_len: (out.len() - start) as u64,
+            });
+        }
+
+        let tmp = temp_path(&self.path)?;
+        fs::write(&tmp, &out)?;
+        fs::rename(&tmp, &self.path)?;
+        self.file = OpenOptions::new().read(true).write(true).open(&self.path)?;
+        self.index = index;
+        self.end = out.len() as u64;
+        self.live_bytes = self.end;
+        Ok(())
+    }
+}
+
+/// When a put() reaches the disk tier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteMode {
+    /// Immediately: disk always holds every entry.
+    WriteThrough,
+    /// When the entry is evicted from memory, or on flush(). Repeated writes to
+    /// a hot key cost no I/O, but unflushed entries are lost if the process dies.
+    WriteBack,
+}
+
+/// LFUCache in front of a DiskStore. Entries LFUCache evicts are spilled to disk
+/// instead of dropped, and a memory miss falls through to disk.
+pub struct TieredCache<K, V> {
+    memory: LFUCache<K, V>,
+    disk: DiskStore<K, V>,
+    mode: WriteMode,
+    // Write-back only: keys whose value in memory (or in `unwritten`) is newer than the one on disk
+    dirty: HashSet<K>,
+    // Write-back only: dirty entries evicted from memory whose disk write failed.
+    // Still dirty; get() serves them and flush() retries them.
+    unwritten: HashMap<K, V>,
+}
+
+impl<K: Hash + Eq + Clone + SnapshotCodec, V: Clone + SnapshotCodec> TieredCache<K, V> {
+    /// `capacity` entries in memory; the disk tier at `path` is unbounded.
+    pub fn open<P: AsRef<Path>>(capacity: usize, path: P, mode: WriteMode) -> io::Result<Self> {
+        Ok(TieredCache {
+            memory: LFUCache::new(capacity),
+            disk: DiskStore::open(path)?,
+            mode,
+            dirty: HashSet::new(),
+            unwritten: HashMap::new(),
+        })
+    }
+
+    /// Look in memory, then on disk. A value found on disk is promoted into
+    /// memory, which may spill another entry.
+    pub fn get(&mut self, key: &K) -> io::Result<Option<V>> {
+        if let Some(value) = self.memory.get(key) {
+            return Ok(Some(value.clone()));
+        }
+        // Evicted but not yet on disk: newer than anything the disk holds
+        if let Some(value) = self.unwritten.get(key) {
+            return Ok(Some(value.clone()));
+        }
+        let value = match self.disk.get(key)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        // The disk copy stays, so the promoted entry is clean
+        let evicted = self.memory.put(key.clone(), value.clone());
+        self.spill(evicted)?;
+        Ok(Some(value))
+    }
+
+    pub fn put(&mut self, key: K, value: V) -> io::Result<()> {
+        match self.mode {
+            WriteMode::WriteThrough => self.disk.put(key.clone(), &value)?,
+            WriteMode::WriteBack => {
+                self.unwritten.remove(&key);
+                self.dirty.insert(key.clone());
+            }
+        }
+        // A pair memory cannot hold comes back here too, and is spilled like a victim
+        let evicted = self.memory.put(key, value);
+        self.spill(evicted)
+    }
+
+    /// Delete `key` from both tiers. Returns whether it was cached in either.
+    pub fn remove(&mut self, key: &K) -> io::Result<bool> {
+        let in_memory = self.memory.remove(key).is_some();
+        let unwritten = self.unwritten.remove(key).is_some();
+        self.dirty.remove(key);
+        let on_disk = self.disk.remove(key)?;
+        Ok(in_memory || unwritten || on_disk)
+    }
+
+    /// Write every dirty entry to disk and sync the file. Call before dropping a
+    /// write-back cache, or entries written since their last eviction are lost.
+    /// Stops at the first failed write; that entry and the ones after it stay
+    /// dirty, so calling flush() again retries them.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let dirty: Vec<K> = self.dirty.iter().cloned().collect();
+        for key in dirty {
+            let value = match self.memory.peek(&key).or_else(|| self.unwritten.get(&key)) {
+                Some(value) => value.clone(),
+                None => {
+                    self.dirty.remove(&key);
+                    continue;
+                }
+            };
+            self.disk.put(key.clone(), &value)?;
+            self.dirty.remove(&key);
+            self.unwritten.remove(&key);
+        }
+        self.disk.sync()
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.memory.contains_key(key) || self.unwritten.contains_key(key) || self.disk.contains_key(key)
+    }
+
+    /// Distinct keys across both tiers.
+    pub fn len(&self) -> usize {
+        let memory_only = self.dirty.iter().filter(|key| !self.disk.contains_key(key)).count();
+        self.disk.len() + memory_only
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The memory tier, e.g. for stats().
+    pub fn memory(&self) -> &LFUCache<K, V> {
+        &self.memory
+    }
+
+    pub fn disk(&self) -> &DiskStore<K, V> {
+        &self.disk
+    }
+
+    // Helper: Write evicted entries that disk does not have yet. Clean ones already
+    // match their disk copy. Every entry is attempted; the first error is returned.
+    // An entry whose write fails stays dirty and is parked in `unwritten`.
+    fn spill(&mut self, evicted: Vec<(K, V)>) -> io::Result<()> {
+        let mut result = Ok(());
+        for (key, value) in evicted {
+            if !self.dirty.contains(&key) {
+                continue;
+            }
+            match self.disk.put(key.clone(), &value) {
+                Ok(()) => {
+                    self.dirty.remove(&key);
+                }
+                Err(e) => {
+                    self.unwritten.insert(key, value);
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("tiered_{}_{}.log", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_disk_store_round_trip_and_reopen() {
+        let path = store_path("reopen");
+        let _ = fs::remove_file(&path);
+        {
+            let mut disk: DiskStore<u32, String> = DiskStore::open(&path).unwrap();
+            disk.put(1, &"one".to_string()).unwrap();
+            disk.put(2, &"two".to_string()).unwrap();
+            disk.put(1, &"uno".to_string()).unwrap();
+            assert!(disk.remove(&2).unwrap());
+            assert!(!disk.remove(&2).unwrap());
+            assert_eq!(disk.get(&1).unwrap(), Some("uno".to_string()));
+            assert_eq!(disk.get(&2).unwrap(), None);
+        }
+        let mut disk: DiskStore<u32, String> = DiskStore::open(&path).unwrap();
+        assert_eq!(disk.len(), 1);
+        assert_eq!(disk.get(&1).unwrap(), Some("uno".to_string()));
+        assert!(!disk.contains_key(&2));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_disk_store_drops_torn_record() {
+        let path = store_path("torn");
+        let _ = fs::remove_file(&path);
+        let good_len = {
+            let mut disk: DiskStore<u32, u64> = DiskStore::open(&path).unwrap();
+            disk.put(1, &10).unwrap();
+            disk.file_len()
+        };
+        // Half a put record, as if the process died mid-write
+        let mut body = vec![OP_PUT];
+        2u32.encode(&mut body);
+        8usize.encode(&mut body);
+        20u64.encode(&mut body);
+        let mut record = Vec::new();
+        write_record(&mut record, &body).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&record[..record.len() / 2]).unwrap();
+        drop(file);
+
+        let mut disk: DiskStore<u32, u64> = DiskStore::open(&path).unwrap();
+        assert_eq!(disk.file_len(), good_len);
+        assert_eq!(fs::metadata(&path).unwrap().len(), good_len);
+        disk.put(3, &30).unwrap();
+        drop(disk);
+        let mut disk: DiskStore<u32, u64> = DiskStore::open(&path).unwrap();
+        assert_eq!(disk.get(&1).unwrap(), Some(10));
+        assert_eq!(disk.get(&3).unwrap(), Some(30));
+        fs::remove_file(&path).unwrap();
+    }
+
+    // One flipped byte early in the log must not cost every record after it
+    #[test]
+    fn test_disk_store_refuses_damaged_record_mid_file() {
+        let path = store_path("damaged");
+        let _ = fs::remove_file(&path);
+        {
+            let mut disk: DiskStore<String, u64> = DiskStore::open(&path).unwrap();
+            for i in 0..101 {
+                disk.put(format!("key{}", i), &i).unwrap();
+            }
+        }
+        let mut bytes = fs::read(&path).unwrap();
+        // First byte of the first key's text: header, op, key length, then the key
+        bytes[RECORD_HEADER + 1 + 8] ^= 0x20;
+        fs::write(&path, &bytes).unwrap();
+
+        let err = DiskStore::<String, u64>::open(&path).err().expect("damaged log should not open");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+        fs::remove_file(&path).unwrap();
+    }
+
+    // A well-formed record with an op that is neither put nor delete
+    #[test]
+    fn test_disk_store_refuses_unknown_op() {
+        let path = store_path("bad_op");
+        let _ = fs::remove_file(&path);
+        {
+            let mut disk: DiskStore<u32, u64> = DiskStore::open(&path).unwrap();
+            disk.put(1, &10).unwrap();
+        }
+        let mut body = vec![7];
+        2u32.encode(&mut body);
+        8usize.encode(&mut body);
+        20u64.encode(&mut body);
+        let mut record = Vec::new();
+        write_record(&mut record, &body).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&record).unwrap();
+        drop(file);
+        let len = fs::metadata(&path).unwrap().len();
+
+        let err = DiskStore::<u32, u64>::open(&path).err().expect("unknown op should not open");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_disk_store_compact_keeps_live_values() {
+        let path = store_path("compact");
+        let _ = fs::remove_file(&path);
+        let mut disk: DiskStore<u32, u64> = DiskStore::open(&path).unwrap();
+        for round in 0..50 {
+            for key in 0..10 {
+                disk.put(key, &(round * 100 + key as u64)).unwrap();
+            }
+        }
+        disk.remove(&9).unwrap();
+        let before = disk.file_len();
+        disk.compact().unwrap();
+        assert!(disk.file_len() < before / 40);
+        for key in 0..9 {
+            assert_eq!(disk.get(&key).unwrap(), Some(4900 + key as u64));
+        }
+        assert_eq!(disk.get(&9).unwrap(), None);
+        drop(disk);
+        let disk: DiskStore<u32, u64> = DiskStore::open(&path).unwrap();
+        assert_eq!(disk.len(), 9);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_write_through_miss_promotes_from_disk() {
+        let path = store_path("write_through");
+        let _ = fs::remove_file(&path);
+        let mut cache: TieredCache<u32, u64> = TieredCache::open(2, &path, WriteMode::WriteThrough).unwrap();
+        cache.put(1, 10).unwrap();
+        cache.put(2, 20).unwrap();
+        cache.get(&2).unwrap();
+        cache.put(3, 30).unwrap(); // evicts 1 from memory
+        assert!(!cache.memory().contains_key(&1));
+        assert_eq!(cache.disk().len(), 3);
+        assert_eq!(cache.len(), 3);
+
+        assert_eq!(cache.get(&1).unwrap(), Some(10));
+        assert!(cache.memory().contains_key(&1));
+        assert!(!cache.memory().contains_key(&3));
+        assert_eq!(cache.get(&4).unwrap(), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_write_back_spills_dirty_entries_on_eviction() {
+        let path = store_path("write_back");
+        let _ = fs::remove_file(&path);
+        let mut cache: TieredCache<u32, u64> = TieredCache::open(2, &path, WriteMode::WriteBack).unwrap();
+        cache.put(1, 10).unwrap();
+        cache.put(1, 11).unwrap();
+        cache.put(2, 20).unwrap();
+        assert!(cache.disk().is_empty());
+        assert_eq!(cache.len(), 2);
+
+        cache.put(3, 30).unwrap(); // evicts 2, the only Freq 1 entry
+        assert_eq!(cache.disk().len(), 1);
+        assert_eq!(cache.get(&2).unwrap(), Some(20));
+        cache.flush().unwrap();
+        drop(cache);
+
+        let mut reopened: TieredCache<u32, u64> = TieredCache::open(2, &path, WriteMode::WriteBack).unwrap();
+        assert_eq!(reopened.len(), 3);
+        assert_eq!(reopened.get(&1).unwrap(), Some(11));
+        assert_eq!(reopened.get(&3).unwrap(), Some(30));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_remove_clears_both_tiers() {
+        let path = store_path("remove");
+        let _ = fs::remove_file(&path);
+        let mut cache: TieredCache<u32, u64> = TieredCache::open(1, &path, WriteMode::WriteThrough).unwrap();
+        cache.put(1, 10).unwrap();
+        cache.put(2, 20).unwrap();
+        assert!(cache.remove(&1).unwrap());
+        assert!(cache.remove(&2).unwrap());
+        assert!(!cache.remove(&2).unwrap());
+        assert!(cache.is_empty());
+        assert_eq!(cache.get(&1).unwrap(), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    // Swap the log's handle for a read-only one, so every write fails until restored
+    fn break_writes(cache: &mut TieredCache<u32, u64>, path: &Path) {
+        cache.disk.file = File::open(path).unwrap();
+    }
+
+    fn restore_writes(cache: &mut TieredCache<u32, u64>, path: &Path) {
+        cache.disk.file = OpenOptions::new().read(true).write(true).open(path).unwrap();
+    }
+
+    #[test]
+    fn test_failed_flush_keeps_entries_dirty() {
+        let path = store_path("flush_fails");
+        let _ = fs::remove_file(&path);
+        let mut cache: TieredCache<u32, u64> = TieredCache::open(4, &path, WriteMode::WriteBack).unwrap();
+        for key in 1..=3 {
+            cache.put(key, key as u64 * 10).unwrap();
+        }
+        break_writes(&mut cache, &path);
+        assert!(cache.flush().is_err());
+        assert_eq!(cache.dirty.len(), 3);
+
+        restore_writes(&mut cache, &path);
+        cache.flush().unwrap();
+        assert!(cache.dirty.is_empty());
+        drop(cache);
+        let reopened: DiskStore<u32, u64> = DiskStore::open(&path).unwrap();
+        assert_eq!(reopened.len(), 3);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_failed_spill_keeps_evicted_value() {
+        let path = store_path("spill_fails");
+        let _ = fs::remove_file(&path);
+        let mut cache: TieredCache<u32, u64> = TieredCache::open(1, &path, WriteMode::WriteBack).unwrap();
+        cache.put(1, 10).unwrap();
+        break_writes(&mut cache, &path);
+        assert!(cache.put(2, 20).is_err()); // evicts dirty 1, whose write fails
+        assert!(!cache.memory().contains_key(&1));
+        assert_eq!(cache.get(&1).unwrap(), Some(10));
+        assert_eq!(cache.len(), 2);
+
+        restore_writes(&mut cache, &path);
+        cache.flush().unwrap();
+        drop(cache);
+        let mut reopened: DiskStore<u32, u64> = DiskStore::open(&path).unwrap();
+        assert_eq!(reopened.get(&1).unwrap(), Some(10));
+        assert_eq!(reopened.get(&2).unwrap(), Some(20));
+        fs::remove_file(&path).unwrap();
+    }
+
+    // Zero capacity: memory hands every pair straight back, so it all lives on disk
+    #[test]
+    fn test_write_back_zero_capacity_goes_straight_to_disk() {
+        let path = store_path("zero");
+        let _ = fs::remove_file(&path);
+        let mut cache: TieredCache<u32, u64> = TieredCache::open(0, &path, WriteMode::WriteBack).unwrap();
+        cache.put(1, 10).unwrap();
+        assert!(cache.disk().contains_key(&1));
+        assert_eq!(cache.get(&1).unwrap(), Some(10));
+        fs::remove_file(&path).unwrap();
+    }
+}